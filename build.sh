@@ -7,6 +7,8 @@ PROJECT_DIR=/home/pi/gruber
 PI_TARGET=armv7-unknown-linux-gnueabihf
 FILES="gruber.service config.json target/$PI_TARGET/release/gruber"
 
+# Catch config mistakes before they make it to the device
+cargo run --quiet -- --config config.json check-config
 cross build --release --target $PI_TARGET
 rsync -r -vv $FILES $PI_HOST:$PROJECT_DIR
 
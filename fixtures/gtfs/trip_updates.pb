@@ -0,0 +1,21 @@
+
+
+2.0&
+1!
+
+trip-1Å섫"a-1
+2
+
+trip-2 *10"a-2.
+3)
+
+trip-3*10"a-1("stop-b*
+4%
+
+trip-9*99"a-1,
+5%
+
+trip-4*10"a-1,
+6'
+
+trip-5 *10"a-1
\ No newline at end of file
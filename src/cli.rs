@@ -0,0 +1,98 @@
+use crate::{config::Config, services::http::Backend};
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+/// Raspberry Pi touchscreen GUI
+#[derive(Debug, Parser)]
+#[clap(author, version, about)]
+pub struct Args {
+    /// Path to the config file. If omitted, look for
+    /// `$XDG_CONFIG_HOME/gruber/config.json`, then `./config.json`
+    #[clap(long, env = "GRUBER_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// Run offline, loading canned API responses from a directory of
+    /// fixtures instead of making HTTP requests. Defaults to `fixtures`;
+    /// pass another directory with `--mock=DIR`
+    #[clap(
+        long,
+        value_name = "DIR",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "fixtures",
+        group = "backend"
+    )]
+    pub mock: Option<PathBuf>,
+    /// Save every API response, with headers and timing, to a directory
+    #[clap(long, value_name = "DIR", group = "backend")]
+    pub record: Option<PathBuf>,
+    /// Run offline, replaying API responses previously saved with `--record`
+    #[clap(long, value_name = "DIR", group = "backend")]
+    pub replay: Option<PathBuf>,
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+impl Args {
+    /// Get the source of HTTP responses, based on the given flags
+    pub fn backend(&self) -> Backend {
+        if let Some(dir) = &self.mock {
+            Backend::Mock(dir.clone())
+        } else if let Some(dir) = &self.record {
+            Backend::Record(dir.clone())
+        } else if let Some(dir) = &self.replay {
+            Backend::Replay(dir.clone())
+        } else {
+            Backend::Live
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Load and validate the config file, then exit
+    CheckConfig,
+}
+
+impl Command {
+    /// Execute a subcommand
+    pub fn execute(self, config_path: PathBuf) -> anyhow::Result<()> {
+        match self {
+            Self::CheckConfig => {
+                let config = Config::parse(&config_path)?;
+                let errors = config.validate();
+                if errors.is_empty() {
+                    println!("Config `{}` is valid", config_path.display());
+                    Ok(())
+                } else {
+                    for error in &errors {
+                        eprintln!("error: {error}");
+                    }
+                    anyhow::bail!(
+                        "Config `{}` has {} error(s)",
+                        config_path.display(),
+                        errors.len()
+                    )
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `--mock` takes an optional directory, which shouldn't swallow the
+    /// subcommand
+    #[test]
+    fn test_mock_dir() {
+        let args =
+            Args::try_parse_from(["gruber", "--mock", "check-config"]).unwrap();
+        assert_eq!(args.mock.as_deref(), Some("fixtures".as_ref()));
+        assert!(matches!(args.command, Some(Command::CheckConfig)));
+
+        let args =
+            Args::try_parse_from(["gruber", "--mock=recordings"]).unwrap();
+        assert_eq!(args.mock.as_deref(), Some("recordings".as_ref()));
+    }
+}
@@ -1,27 +1,575 @@
-use crate::services::transit::TransitLine;
+use crate::{
+    logging::LogConfig,
+    services::{
+        ExternalData, SERVICE_NAMES,
+        bikes::BikeShareConfig,
+        transit::{TransitLine, TransitProvider},
+    },
+};
 use anyhow::Context;
+use chrono::NaiveTime;
+use itertools::Itertools;
 use log::info;
-use serde::Deserialize;
-use std::fs::File;
+use serde::{Deserialize, Deserializer};
+use std::{
+    collections::HashMap,
+    env,
+    fs::File,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 #[derive(Debug, Deserialize)]
 pub struct Config {
     pub window_size: (f32, f32),
     /// Optionally force the position of the opening window
     pub window_position: Option<(f32, f32)>,
-    pub forecast_office: String,
-    pub forecast_gridpoint: (u32, u32),
+    /// Location to show the weather forecast for. This is resolved to an NWS
+    /// forecast office and gridpoint through the weather.gov API
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    /// NWS forecast office, e.g. `BOX`. If set along with
+    /// `forecast_gridpoint`, this overrides the location lookup
+    pub forecast_office: Option<String>,
+    pub forecast_gridpoint: Option<(u32, u32)>,
     pub transit_lines: Vec<TransitLine>,
+    /// Where to get transit predictions from. Defaults to the MBTA
+    #[serde(default)]
+    pub transit_provider: TransitProvider,
+    /// Key for the MBTA API, which raises the rate limit. Overridden by
+    /// `MBTA_API_KEY`
+    pub mbta_api_key: Option<String>,
+    /// Stream transit predictions instead of polling for them. If the stream
+    /// drops, polling is used until it reconnects
+    #[serde(default = "Config::default_transit_streaming")]
+    pub transit_streaming: bool,
+    /// Show transit countdowns to the second (e.g. `4:32`), instead of whole
+    /// minutes
+    #[serde(default)]
+    pub transit_countdown_seconds: bool,
+    /// Bike share stations to show availability for. The Bikes tab is hidden
+    /// if this isn't set
+    pub bike_share: Option<BikeShareConfig>,
+    /// Override how often each service refreshes its data
+    #[serde(default)]
+    pub refresh: RefreshConfig,
+    /// Data older than this many refresh intervals is flagged as stale in the
+    /// UI
+    #[serde(default = "Config::default_stale_multiplier")]
+    pub stale_multiplier: f64,
+    #[serde(default)]
+    pub logging: LogConfig,
+    /// Directory to save the most recent data from each service, so it can
+    /// be shown immediately on startup. Defaults to
+    /// `$XDG_CACHE_HOME/gruber`. Changes to this require a restart
+    pub cache_dir: Option<PathBuf>,
 }
 
 impl Config {
-    const PATH: &'static str = "./config.json";
+    const FILE_NAME: &'static str = "config.json";
+    /// Upper bound for each forecast gridpoint coordinate. NWS forecast grids
+    /// are at most a few hundred cells across, so anything past this is
+    /// almost certainly a typo
+    const MAX_GRIDPOINT: u32 = 1000;
+
+    fn default_stale_multiplier() -> f64 {
+        3.0
+    }
+
+    fn default_transit_streaming() -> bool {
+        true
+    }
+
+    /// Get the path to the config file. If an override is given (from the CLI
+    /// or environment), use that. Otherwise use the XDG config directory if
+    /// the file exists there, and fall back to the current directory.
+    pub fn path(override_path: Option<PathBuf>) -> PathBuf {
+        override_path
+            .or_else(|| {
+                let config_dir = env::var_os("XDG_CONFIG_HOME")
+                    .map(PathBuf::from)
+                    .or_else(|| {
+                        env::var_os("HOME")
+                            .map(|home| PathBuf::from(home).join(".config"))
+                    })?;
+                let path = config_dir.join("gruber").join(Self::FILE_NAME);
+                path.exists().then_some(path)
+            })
+            .unwrap_or_else(|| Path::new(".").join(Self::FILE_NAME))
+    }
+
+    /// Get the path of the file to cache a service's data in. `None` if there's
+    /// no cache directory configured and no home directory to default to
+    pub fn cache_path<S: ExternalData>(&self) -> Option<PathBuf> {
+        let cache_dir = self.cache_dir.clone().or_else(|| {
+            let cache_home = env::var_os("XDG_CACHE_HOME")
+                .map(PathBuf::from)
+                .or_else(|| {
+                env::var_os("HOME").map(|home| Path::new(&home).join(".cache"))
+            })?;
+            Some(cache_home.join("gruber"))
+        })?;
+        Some(cache_dir.join(format!("{}.json", S::NAME)))
+    }
 
     /// Load config from file
-    pub fn load() -> anyhow::Result<Self> {
-        info!("Loading config from `{}`", Self::PATH);
-        let file = File::open(Self::PATH)?;
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let config = Self::parse(path)?;
+        let errors = config.validate();
+        if !errors.is_empty() {
+            anyhow::bail!(
+                "Invalid config file `{}`: {}",
+                path.display(),
+                errors.join("; ")
+            );
+        }
+        Ok(config)
+    }
+
+    /// Parse the config file without validating it. Use [Self::load] unless
+    /// you need to report validation errors yourself
+    pub fn parse(path: &Path) -> anyhow::Result<Self> {
+        info!("Loading config from `{}`", path.display());
+        let file = File::open(path).context(format!(
+            "Error opening config file `{}`",
+            path.display()
+        ))?;
         serde_json::from_reader(file)
-            .context(format!("Error parsing config file {}", Self::PATH))
+            .context(format!("Error parsing config file `{}`", path.display()))
+    }
+
+    /// Check the config for values that parse but are obviously wrong. Return
+    /// a human-readable message for each problem found
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        match (self.latitude, self.longitude) {
+            (Some(latitude), Some(longitude)) => {
+                if !(-90.0..=90.0).contains(&latitude) {
+                    errors.push(format!(
+                        "latitude `{latitude}` is out of range; it must be \
+                        between -90 and 90"
+                    ));
+                }
+                if !(-180.0..=180.0).contains(&longitude) {
+                    errors.push(format!(
+                        "longitude `{longitude}` is out of range; it must be \
+                        between -180 and 180"
+                    ));
+                }
+            }
+            (None, None) => {}
+            _ => errors
+                .push("latitude and longitude must be set together".to_owned()),
+        }
+        if let Some(office) = &self.forecast_office {
+            if office.len() != 3
+                || !office.chars().all(|c| c.is_ascii_uppercase())
+            {
+                errors.push(format!(
+                    "forecast_office `{office}` should be a three-letter NWS \
+                    office code, e.g. `BOX`"
+                ));
+            }
+        }
+        if let Some((x, y)) = self.forecast_gridpoint {
+            if x > Self::MAX_GRIDPOINT || y > Self::MAX_GRIDPOINT {
+                errors.push(format!(
+                    "forecast_gridpoint `{x},{y}` is out of range; each \
+                    coordinate should be at most {}",
+                    Self::MAX_GRIDPOINT
+                ));
+            }
+        }
+        if self.forecast_office.is_some() != self.forecast_gridpoint.is_some() {
+            errors.push(
+                "forecast_office and forecast_gridpoint must be set together"
+                    .to_owned(),
+            );
+        }
+        if self.latitude.is_none() && self.forecast_office.is_none() {
+            errors.push(
+                "set latitude and longitude, or forecast_office and \
+                forecast_gridpoint, to show the weather forecast"
+                    .to_owned(),
+            );
+        }
+
+        if self.stale_multiplier.is_nan() || self.stale_multiplier < 1.0 {
+            errors.push(format!(
+                "stale_multiplier `{}` should be at least 1, otherwise data \
+                is flagged as stale before it's refreshed",
+                self.stale_multiplier
+            ));
+        }
+
+        for line in &self.transit_lines {
+            if line.stops.is_empty() {
+                errors
+                    .push(format!("transit line `{}` has no stops", line.name));
+            }
+            for (i, stop) in line.stops.iter().enumerate() {
+                if stop.id.trim().is_empty() {
+                    errors.push(format!(
+                        "transit line `{}` stop `{}` has an empty ID",
+                        line.name, stop.name
+                    ));
+                }
+                // The same stop can appear twice with different filters, e.g.
+                // to split a station by direction
+                if line.stops[..i].iter().any(|other| {
+                    other.id == stop.id
+                        && other.direction_id == stop.direction_id
+                        && other.routes == stop.routes
+                }) {
+                    errors.push(format!(
+                        "transit line `{}` has duplicate stop ID `{}`",
+                        line.name, stop.id
+                    ));
+                }
+                if stop.direction_id.is_some_and(|direction| direction > 1) {
+                    errors.push(format!(
+                        "transit line `{}` stop `{}` has direction_id `{}`; \
+                        it must be 0 or 1",
+                        line.name,
+                        stop.name,
+                        stop.direction_id.unwrap_or_default()
+                    ));
+                }
+                if stop.max_predictions == 0 {
+                    errors.push(format!(
+                        "transit line `{}` stop `{}` has max_predictions `0`; \
+                        it must be at least 1",
+                        line.name, stop.name
+                    ));
+                }
+                for route in &stop.routes {
+                    if !line.has_route(route) {
+                        errors.push(format!(
+                            "transit line `{}` stop `{}` filters on route \
+                            `{route}`, which isn't on the line",
+                            line.name, stop.name
+                        ));
+                    }
+                }
+            }
+        }
+
+        if let Some(bike_share) = &self.bike_share {
+            if bike_share.stations.is_empty() {
+                errors.push("bike_share has no stations".to_owned());
+            }
+            for (i, station) in bike_share.stations.iter().enumerate() {
+                if bike_share.stations[..i]
+                    .iter()
+                    .any(|other| other.id == station.id)
+                {
+                    errors.push(format!(
+                        "bike_share has duplicate station ID `{}`",
+                        station.id
+                    ));
+                }
+            }
+        }
+
+        // Overrides for unknown services would be silently ignored
+        for service in self.refresh.services.keys().sorted() {
+            if !SERVICE_NAMES.contains(&service.as_str()) {
+                errors.push(format!(
+                    "refresh.{service}: unknown service; expected one of {}",
+                    SERVICE_NAMES.iter().format(", ")
+                ));
+            }
+        }
+        for (service, schedule) in
+            self.refresh
+                .default
+                .iter()
+                .map(|schedule| ("default", schedule))
+                .chain(
+                    self.refresh.services.iter().map(|(service, schedule)| {
+                        (service.as_str(), schedule)
+                    }),
+                )
+        {
+            errors.extend(
+                schedule
+                    .validate()
+                    .map(|error| format!("refresh.{service}: {error}")),
+            );
+        }
+
+        errors
+    }
+}
+
+/// Refresh intervals for external data services
+#[derive(Debug, Default, Deserialize)]
+pub struct RefreshConfig {
+    /// Schedule for any service without its own override. If omitted, each
+    /// service uses its built-in default
+    pub default: Option<RefreshSchedule>,
+    /// Per-service overrides, keyed by service name (e.g. `transit`)
+    #[serde(flatten)]
+    pub services: HashMap<String, RefreshSchedule>,
+}
+
+impl RefreshConfig {
+    /// Get the refresh schedule for a service
+    pub fn schedule<S: ExternalData>(&self) -> RefreshSchedule {
+        self.services
+            .get(S::NAME)
+            .or(self.default.as_ref())
+            .cloned()
+            .unwrap_or_else(|| RefreshSchedule::fixed(S::DEFAULT_TTL))
+    }
+}
+
+/// How often to refresh a service's data. The interval can vary by time of
+/// day, e.g. to refresh frequently during commute hours and rarely overnight.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct RefreshSchedule {
+    /// Refresh interval, in seconds, for any time not covered by a window
+    #[serde(deserialize_with = "deserialize_seconds")]
+    pub interval: Duration,
+    /// Time-of-day windows with their own interval. If windows overlap, the
+    /// first match wins
+    #[serde(default)]
+    pub windows: Vec<RefreshWindow>,
+}
+
+impl RefreshSchedule {
+    /// A schedule with the same interval all day
+    pub fn fixed(interval: Duration) -> Self {
+        Self {
+            interval,
+            windows: Vec::new(),
+        }
+    }
+
+    /// Check for zero intervals and empty windows
+    fn validate(&self) -> impl Iterator<Item = String> {
+        let interval_error = self
+            .interval
+            .is_zero()
+            .then(|| "interval must be greater than zero".to_owned());
+        let window_errors = self.windows.iter().filter_map(|window| {
+            if window.interval.is_zero() {
+                Some(format!(
+                    "window {}-{}: interval must be greater than zero",
+                    window.start, window.end
+                ))
+            } else if window.start == window.end {
+                Some(format!(
+                    "window {}-{}: start and end must differ",
+                    window.start, window.end
+                ))
+            } else {
+                None
+            }
+        });
+        interval_error.into_iter().chain(window_errors)
+    }
+
+    /// Get the refresh interval that applies at the given local time
+    pub fn ttl(&self, time: NaiveTime) -> Duration {
+        self.windows
+            .iter()
+            .find(|window| window.contains(time))
+            .map(|window| window.interval)
+            .unwrap_or(self.interval)
+    }
+}
+
+/// A time-of-day range with its own refresh interval
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct RefreshWindow {
+    /// Start of the window (inclusive), e.g. `06:30`
+    pub start: NaiveTime,
+    /// End of the window (exclusive). If this is before `start`, the window
+    /// wraps past midnight
+    pub end: NaiveTime,
+    /// Refresh interval, in seconds, within this window
+    #[serde(deserialize_with = "deserialize_seconds")]
+    pub interval: Duration,
+}
+
+impl RefreshWindow {
+    fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            // Window wraps past midnight
+            self.start <= time || time < self.end
+        }
+    }
+}
+
+/// Deserialize a duration from a whole number of seconds
+fn deserialize_seconds<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    u64::deserialize(deserializer).map(Duration::from_secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(s: &str) -> NaiveTime {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_validate() {
+        let config: Config = serde_json::from_str(
+            r#"{
+                "window_size": [720, 720],
+                "latitude": 42.36,
+                "forecast_office": "box",
+                "forecast_gridpoint": [71, 9000],
+                "transit_lines": [
+                    {
+                        "name": "1",
+                        "stops": [
+                            {"name": "Inbound", "id": 2545},
+                            {"name": "Outbound", "id": 2545},
+                            {"name": "Inbound", "id": "2545", "direction_id": 1},
+                            {"name": "Sideways", "id": "2570", "direction_id": 2},
+                            {"name": "Other", "id": "2570", "routes": ["47"]},
+                            {
+                                "name": "None",
+                                "id": "2570",
+                                "direction_id": 0,
+                                "max_predictions": 0
+                            }
+                        ]
+                    },
+                    {"name": "47", "stops": []}
+                ],
+                "bike_share": {
+                    "station_information_url": "https://example.com/info.json",
+                    "station_status_url": "https://example.com/status.json",
+                    "stations": [{"id": 67}, {"name": "Home", "id": "67"}]
+                },
+                "refresh": {"transit": {"interval": 0}, "trasit": {"interval": 5}}
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.validate(),
+            vec![
+                "latitude and longitude must be set together",
+                "forecast_office `box` should be a three-letter NWS office \
+                code, e.g. `BOX`",
+                "forecast_gridpoint `71,9000` is out of range; each \
+                coordinate should be at most 1000",
+                "transit line `1` has duplicate stop ID `2545`",
+                "transit line `1` stop `Sideways` has direction_id `2`; it \
+                must be 0 or 1",
+                "transit line `1` stop `Other` filters on route `47`, which \
+                isn't on the line",
+                "transit line `1` stop `None` has max_predictions `0`; it \
+                must be at least 1",
+                "transit line `47` has no stops",
+                "bike_share has duplicate station ID `67`",
+                "refresh.trasit: unknown service; expected one of weather, \
+                weather_point, transit, schedules, alerts, gtfs_static, bikes",
+                "refresh.transit: interval must be greater than zero",
+            ]
+        );
+    }
+
+    /// Loading should reject configs that parse but fail validation, so bad
+    /// values never reach the running app
+    #[test]
+    fn test_load_validates() {
+        let path = env::temp_dir()
+            .join(format!("gruber-test-config-{}.json", std::process::id()));
+        let load = |refresh: &str| {
+            std::fs::write(
+                &path,
+                format!(
+                    r#"{{
+                        "window_size": [720, 720],
+                        "forecast_office": "BOX",
+                        "forecast_gridpoint": [71, 90],
+                        "transit_lines": [],
+                        "refresh": {refresh}
+                    }}"#
+                ),
+            )
+            .unwrap();
+            let result = Config::load(&path);
+            std::fs::remove_file(&path).unwrap();
+            result
+        };
+
+        load(r#"{"transit": {"interval": 10}}"#).unwrap();
+        assert_eq!(
+            load(r#"{"transit": {"interval": 0}}"#)
+                .unwrap_err()
+                .to_string(),
+            format!(
+                "Invalid config file `{}`: refresh.transit: interval must be \
+                greater than zero",
+                path.display()
+            )
+        );
+    }
+
+    #[test]
+    fn test_validate_location() {
+        let validate = |location: &str| {
+            serde_json::from_str::<Config>(&format!(
+                r#"{{"window_size": [720, 720], "transit_lines": []{location}}}"#
+            ))
+            .unwrap()
+            .validate()
+        };
+
+        assert_eq!(
+            validate(""),
+            vec![
+                "set latitude and longitude, or forecast_office and \
+                forecast_gridpoint, to show the weather forecast"
+            ]
+        );
+        assert_eq!(
+            validate(r#", "latitude": 91.0, "longitude": -71.0"#),
+            vec![
+                "latitude `91` is out of range; it must be between -90 and 90"
+            ]
+        );
+        assert_eq!(
+            validate(r#", "forecast_office": "BOX""#),
+            vec!["forecast_office and forecast_gridpoint must be set together"]
+        );
+        assert!(
+            validate(r#", "latitude": 42.36, "longitude": -71.06"#).is_empty()
+        );
+    }
+
+    #[test]
+    fn test_refresh_schedule() {
+        let schedule: RefreshSchedule = serde_json::from_str(
+            r#"{
+                "interval": 30,
+                "windows": [
+                    {"start": "07:00", "end": "09:30", "interval": 15},
+                    {"start": "23:00", "end": "05:00", "interval": 300}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(schedule.ttl(time("06:59")), Duration::from_secs(30));
+        assert_eq!(schedule.ttl(time("07:00")), Duration::from_secs(15));
+        assert_eq!(schedule.ttl(time("09:30")), Duration::from_secs(30));
+        assert_eq!(schedule.ttl(time("23:30")), Duration::from_secs(300));
+        assert_eq!(schedule.ttl(time("02:00")), Duration::from_secs(300));
+        assert_eq!(schedule.ttl(time("05:00")), Duration::from_secs(30));
     }
 }
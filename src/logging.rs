@@ -0,0 +1,250 @@
+use anyhow::Context;
+use chrono::Local;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde::Deserialize;
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Mutex, OnceLock},
+};
+
+/// Prefix of log targets from this crate. Logs from other crates (iced, wgpu,
+/// etc.) are very noisy, so they're capped at [LogConfig::DEPENDENCY_LEVEL]
+const CRATE_TARGET: &str = env!("CARGO_CRATE_NAME");
+
+/// File to write logs to, in addition to stderr. Opened by [configure]
+static LOG_FILE: OnceLock<Mutex<RotatingFile>> = OnceLock::new();
+
+/// Logging configuration
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+pub struct LogConfig {
+    /// Max level of logs from this program. Overridden by `RUST_LOG`
+    pub level: Option<LevelFilter>,
+    /// Write logs to a file, in addition to stderr. Changes to this require a
+    /// restart
+    pub file: Option<LogFileConfig>,
+}
+
+impl LogConfig {
+    const DEFAULT_LEVEL: LevelFilter = LevelFilter::Info;
+    const DEPENDENCY_LEVEL: LevelFilter = LevelFilter::Warn;
+
+    /// Get the level to log at. `RUST_LOG` takes precedence over the config.
+    /// Only a single level (e.g. `debug`) is supported, not per-module filters
+    fn level(&self) -> LevelFilter {
+        env::var("RUST_LOG")
+            .ok()
+            .and_then(|level| LevelFilter::from_str(&level).ok())
+            .or(self.level)
+            .unwrap_or(Self::DEFAULT_LEVEL)
+    }
+}
+
+/// Configuration for logging to a file. The file is rotated when it exceeds a
+/// max size: `gruber.log` becomes `gruber.log.1`, `gruber.log.1` becomes
+/// `gruber.log.2`, and so on.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct LogFileConfig {
+    pub path: PathBuf,
+    /// Max size of the log file, in bytes, before it's rotated
+    #[serde(default = "LogFileConfig::default_max_size")]
+    pub max_size: u64,
+    /// Number of rotated files to keep, not including the active one
+    #[serde(default = "LogFileConfig::default_max_files")]
+    pub max_files: u32,
+}
+
+impl LogFileConfig {
+    fn default_max_size() -> u64 {
+        1024 * 1024
+    }
+
+    fn default_max_files() -> u32 {
+        3
+    }
+}
+
+/// Install the global logger, logging to stderr at the default level. This
+/// should be called once at startup, before the config is loaded so nothing
+/// logged while loading it is lost
+pub fn initialize() -> anyhow::Result<()> {
+    let logger = Logger {
+        // systemd sets this when stderr is connected to the journal
+        journald: env::var_os("JOURNAL_STREAM").is_some(),
+    };
+    log::set_boxed_logger(Box::new(logger))?;
+    set_level(&LogConfig::default());
+    Ok(())
+}
+
+/// Apply the config loaded at startup: set the level and open the log file,
+/// if there is one. Only logs after this are written to the file
+pub fn configure(config: &LogConfig) -> anyhow::Result<()> {
+    if let Some(file_config) = &config.file {
+        let file = RotatingFile::open(file_config.clone()).context(format!(
+            "Error opening log file `{}`",
+            file_config.path.display()
+        ))?;
+        // This is only called once, so the file can't already be set
+        let _ = LOG_FILE.set(Mutex::new(file));
+    }
+    set_level(config);
+    Ok(())
+}
+
+/// Update the max log level from the config. This can be called at any time,
+/// e.g. after a config reload
+pub fn set_level(config: &LogConfig) {
+    log::set_max_level(config.level());
+}
+
+/// Logger that writes to stderr and optionally a rotating file
+struct Logger {
+    /// If enabled, prefix stderr lines with their syslog priority and omit
+    /// the timestamp, so journald can record both natively
+    journald: bool,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        // The global max level is checked by the log macros, so we only need
+        // to filter out dependencies
+        metadata.target().starts_with(CRATE_TARGET)
+            || metadata.level() <= LogConfig::DEPENDENCY_LEVEL
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let timestamp = Local::now().format("%Y-%m-%dT%H:%M:%S%.3f");
+        let line = format!(
+            "{:<5} {}: {}",
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        // Logging errors have nowhere to go, so they're ignored
+        let _ = if self.journald {
+            writeln!(
+                io::stderr(),
+                "<{}>{line}",
+                syslog_priority(record.level())
+            )
+        } else {
+            writeln!(io::stderr(), "{timestamp} {line}")
+        };
+        if let Some(file) = LOG_FILE.get() {
+            let mut file =
+                file.lock().unwrap_or_else(|error| error.into_inner());
+            let _ = file.write_line(&format!("{timestamp} {line}"));
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(file) = LOG_FILE.get() {
+            let mut file =
+                file.lock().unwrap_or_else(|error| error.into_inner());
+            let _ = file.file.flush();
+        }
+    }
+}
+
+/// Map a log level to a syslog priority, for journald's stderr parsing
+fn syslog_priority(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// A log file that's rotated when it exceeds a max size
+struct RotatingFile {
+    config: LogFileConfig,
+    file: File,
+    /// Current size of the active file
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(config: LogFileConfig) -> io::Result<Self> {
+        let file = Self::open_file(&config.path)?;
+        let size = file.metadata()?.len();
+        Ok(Self { config, file, size })
+    }
+
+    fn open_file(path: &Path) -> io::Result<File> {
+        OpenOptions::new().create(true).append(true).open(path)
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if self.size >= self.config.max_size {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{line}")?;
+        self.size += line.len() as u64 + 1;
+        Ok(())
+    }
+
+    /// Shift each rotated file up by one, dropping the oldest, then start a
+    /// fresh active file
+    fn rotate(&mut self) -> io::Result<()> {
+        let path = &self.config.path;
+        let rotated_path = |i: u32| {
+            let mut path = path.clone().into_os_string();
+            path.push(format!(".{i}"));
+            PathBuf::from(path)
+        };
+        for i in (1..self.config.max_files).rev() {
+            let from = rotated_path(i);
+            if from.exists() {
+                fs::rename(from, rotated_path(i + 1))?;
+            }
+        }
+        if self.config.max_files > 0 {
+            fs::rename(path, rotated_path(1))?;
+        } else {
+            fs::remove_file(path)?;
+        }
+        self.file = Self::open_file(path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotate() {
+        let dir =
+            env::temp_dir().join(format!("gruber-log-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("gruber.log");
+        let mut file = RotatingFile::open(LogFileConfig {
+            path: path.clone(),
+            max_size: 10,
+            max_files: 2,
+        })
+        .unwrap();
+
+        // Each line fills the file, so every write after the first rotates
+        for line in ["first line", "second line", "third line", "fourth line"] {
+            file.write_line(line).unwrap();
+        }
+
+        let read = |name: &str| fs::read_to_string(dir.join(name)).unwrap();
+        assert_eq!(read("gruber.log"), "fourth line\n");
+        assert_eq!(read("gruber.log.1"), "third line\n");
+        assert_eq!(read("gruber.log.2"), "second line\n");
+        assert!(!dir.join("gruber.log.3").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,17 +1,42 @@
+mod cli;
 mod config;
+mod logging;
 mod services;
 mod view;
 
 use crate::{
+    cli::Args,
     config::Config,
-    services::{ExternalData, FetchedData, transit::Transit, weather::Weather},
+    services::{
+        ExternalData, FetchError, FetchResult,
+        bikes::Bikes,
+        transit::{Alerts, GtfsStatic, Schedules, StreamEvent, Transit},
+        weather::{Weather, WeatherPoint},
+    },
 };
+use clap::Parser;
 use iced::{Subscription, Task, Theme, window};
-use iced_aw::iced_fonts;
-use std::{fmt::Display, time::Duration};
+use log::{error, info};
+use std::{
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
 
 fn main() -> anyhow::Result<()> {
-    let config = Config::load()?;
+    let args = Args::parse();
+    let backend = args.backend();
+    let config_path = Config::path(args.config);
+    if let Some(command) = args.command {
+        return command.execute(config_path);
+    }
+
+    logging::initialize()?;
+    let config = Config::load(&config_path)?;
+    logging::configure(&config.logging)?;
+    info!("Loaded config from `{}`", config_path.display());
+    backend.set();
 
     let window_settings = window::Settings {
         size: config.window_size.into(),
@@ -22,16 +47,22 @@ fn main() -> anyhow::Result<()> {
         ..window::Settings::default()
     };
     iced::application("Gruber", State::update, view::view)
-        .subscription(State::check_data_subscription)
+        .subscription(State::subscription)
         .settings(iced::Settings {
             default_text_size: 24.0.into(),
             ..iced::Settings::default()
         })
         .font(iced_fonts::REQUIRED_FONT_BYTES)
+        .font(iced_fonts::BOOTSTRAP_FONT_BYTES)
         .resizable(false)
         .window(window_settings)
         .theme(|_| Theme::TokyoNightStorm)
-        .run_with(|| (State::new(config), Task::done(Message::CheckData)))?;
+        .run_with(move || {
+            (
+                State::new(config, config_path),
+                Task::done(Message::CheckData),
+            )
+        })?;
 
     Ok(())
 }
@@ -42,27 +73,76 @@ enum Message {
     /// Periodically check all data to see if it's stale. Anything that is will
     /// be refetched
     CheckData,
+    /// Periodically check if the config file has changed. If it has, reload
+    /// it
+    CheckConfig,
     TabSelected(Tab),
-    WeatherFetched(FetchedData<<Weather as ExternalData>::Data>),
-    TransitFetched(FetchedData<<Transit as ExternalData>::Data>),
+    WeatherFetched(FetchResult<<Weather as ExternalData>::Data>),
+    WeatherPointFetched(FetchResult<<WeatherPoint as ExternalData>::Data>),
+    TransitFetched(FetchResult<<Transit as ExternalData>::Data>),
+    SchedulesFetched(FetchResult<<Schedules as ExternalData>::Data>),
+    AlertsFetched(FetchResult<<Alerts as ExternalData>::Data>),
+    GtfsStaticFetched(FetchResult<<GtfsStatic as ExternalData>::Data>),
+    BikesFetched(FetchResult<<Bikes as ExternalData>::Data>),
+    /// An update from the transit prediction stream, or an error if the
+    /// stream dropped
+    TransitStreamed(Result<StreamEvent, FetchError>),
 }
 
 /// Global app state
 #[derive(Debug)]
 struct State {
     active_tab: Tab,
+    config_path: PathBuf,
+    /// Modification time of the config file when it was last loaded, used to
+    /// detect changes
+    config_modified: Option<SystemTime>,
+    /// Temporary message to show the user, e.g. when a config reload fails
+    toast: Option<Toast>,
+    /// See [Config::stale_multiplier]
+    stale_multiplier: f64,
     weather: Weather,
+    weather_point: WeatherPoint,
     transit: Transit,
+    schedules: Schedules,
+    alerts: Alerts,
+    gtfs_static: GtfsStatic,
+    bikes: Bikes,
 }
 
 impl State {
-    fn new(config: Config) -> Self {
-        let weather = Weather::new(&config);
-        let transit = Transit::new(&config);
+    /// How long a toast message is shown
+    const TOAST_DURATION: Duration = Duration::from_secs(10);
+
+    fn new(config: Config, config_path: PathBuf) -> Self {
+        // The forecast URL depends on the resolved point, so load that first
+        let mut weather_point = WeatherPoint::new(&config);
+        weather_point.load_cache();
+        let mut weather = Weather::new(&config, &weather_point);
+        let mut transit = Transit::new(&config);
+        let mut schedules = Schedules::new(&config);
+        let mut alerts = Alerts::new(&config);
+        let mut gtfs_static = GtfsStatic::new(&config);
+        let mut bikes = Bikes::new(&config);
+        weather.load_cache();
+        transit.load_cache();
+        schedules.load_cache();
+        alerts.load_cache();
+        gtfs_static.load_cache();
+        bikes.load_cache();
         Self {
             active_tab: Tab::Weather,
+            config_modified: modified_time(&config_path),
+            config_path,
+            toast: None,
+            stale_multiplier: config.stale_multiplier,
             weather,
+            weather_point,
             transit,
+            schedules,
+            alerts,
+            gtfs_static,
+            bikes,
         }
     }
 
@@ -73,19 +153,135 @@ impl State {
                 // Check all data sources in parallel
                 return Task::batch([
                     self.weather.fetch_if_needed(),
+                    self.weather_point.fetch_if_needed(),
                     self.transit.fetch_if_needed(),
+                    self.schedules.fetch_if_needed(),
+                    self.alerts.fetch_if_needed(),
+                    self.gtfs_static.fetch_if_needed(),
+                    self.bikes.fetch_if_needed(),
                 ]);
             }
+            Message::CheckConfig => {
+                if self.toast.as_ref().is_some_and(Toast::is_expired) {
+                    self.toast = None;
+                }
+                self.reload_config_if_changed();
+            }
             Message::TabSelected(index) => self.active_tab = index,
-            Message::WeatherFetched(data) => self.weather.set_data(data),
-            Message::TransitFetched(data) => self.transit.set_data(data),
+            Message::WeatherFetched(result) => {
+                self.weather.handle_result(result);
+            }
+            Message::WeatherPointFetched(result) => {
+                self.weather_point.handle_result(result);
+                self.weather.set_point(&self.weather_point);
+            }
+            Message::TransitFetched(result) => {
+                self.transit.handle_result(result);
+            }
+            Message::SchedulesFetched(result) => {
+                self.schedules.handle_result(result);
+            }
+            Message::AlertsFetched(result) => {
+                self.alerts.handle_result(result);
+            }
+            Message::GtfsStaticFetched(result) => {
+                self.gtfs_static.handle_result(result);
+            }
+            Message::BikesFetched(result) => {
+                self.bikes.handle_result(result);
+            }
+            Message::TransitStreamed(result) => {
+                self.transit.handle_stream(result);
+            }
         }
         Task::none()
     }
 
-    /// Create a subscription that will periodically send a `CheckData` message
-    fn check_data_subscription(&self) -> Subscription<Message> {
-        iced::time::every(Duration::from_secs(1)).map(|_| Message::CheckData)
+    /// If the config file has been modified since it was last loaded, reload
+    /// it and rebuild any services whose settings changed. If the new config
+    /// is invalid, keep the old one and show an error.
+    fn reload_config_if_changed(&mut self) {
+        let modified = modified_time(&self.config_path);
+        // If we can't get the mtime (e.g. the file is mid-write), try later
+        if modified.is_none() || modified == self.config_modified {
+            return;
+        }
+        self.config_modified = modified;
+
+        let config = match Config::load(&self.config_path) {
+            Ok(config) => config,
+            Err(error) => {
+                error!("Error reloading config: {error:#}");
+                self.toast =
+                    Some(Toast::new(format!("Config not reloaded: {error:#}")));
+                return;
+            }
+        };
+        info!("Reloaded config from `{}`", self.config_path.display());
+        self.toast = None;
+        logging::set_level(&config.logging);
+        self.stale_multiplier = config.stale_multiplier;
+        self.weather_point.reload(WeatherPoint::new(&config));
+        self.weather
+            .reload(Weather::new(&config, &self.weather_point));
+        self.transit.reload(Transit::new(&config));
+        self.schedules.reload(Schedules::new(&config));
+        self.alerts.reload(Alerts::new(&config));
+        self.gtfs_static.reload(GtfsStatic::new(&config));
+        self.bikes.reload(Bikes::new(&config));
+        // Don't leave the user on a tab that's been hidden
+        if !self.tabs().any(|tab| tab == self.active_tab) {
+            self.active_tab = Tab::Weather;
+        }
+    }
+
+    /// Get the tabs to show. Tabs for services that aren't configured are
+    /// hidden
+    fn tabs(&self) -> impl Iterator<Item = Tab> {
+        Tab::iter().filter(|tab| match tab {
+            Tab::Weather | Tab::Transit => true,
+            Tab::Bikes => self.bikes.is_enabled(),
+        })
+    }
+
+    /// Create subscriptions for periodic checks on data and config, and for
+    /// streamed data
+    fn subscription(&self) -> Subscription<Message> {
+        Subscription::batch([
+            iced::time::every(Duration::from_secs(1))
+                .map(|_| Message::CheckData),
+            iced::time::every(Duration::from_secs(2))
+                .map(|_| Message::CheckConfig),
+            self.transit.subscription(),
+        ])
+    }
+}
+
+/// Get the last modification time of a file, or `None` if it can't be
+/// accessed
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// A temporary message displayed over the content
+#[derive(Debug)]
+struct Toast {
+    message: String,
+    created_at: Instant,
+}
+
+impl Toast {
+    fn new(message: String) -> Self {
+        Self {
+            message,
+            created_at: Instant::now(),
+        }
+    }
+
+    fn is_expired(&self) -> bool {
+        self.created_at.elapsed() > State::TOAST_DURATION
     }
 }
 
@@ -93,11 +289,12 @@ impl State {
 enum Tab {
     Weather,
     Transit,
+    Bikes,
 }
 
 impl Tab {
     fn iter() -> impl Iterator<Item = Self> {
-        [Self::Weather, Self::Transit].into_iter()
+        [Self::Weather, Self::Transit, Self::Bikes].into_iter()
     }
 }
 
@@ -106,6 +303,7 @@ impl Display for Tab {
         match self {
             Tab::Weather => write!(f, "Weather"),
             Tab::Transit => write!(f, "Transit"),
+            Tab::Bikes => write!(f, "Bikes"),
         }
     }
 }
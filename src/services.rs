@@ -1,52 +1,345 @@
+pub mod bikes;
+pub mod http;
+#[cfg(test)]
+mod test_util;
 pub mod transit;
 pub mod weather;
 
-use crate::Message;
+use crate::{Message, config::RefreshSchedule, services::http::Backend};
+use anyhow::Context;
+use chrono::{DateTime, Local, Utc};
 use iced::Task;
+use log::{info, warn};
+use serde::{Deserialize, Deserializer, Serialize, de::DeserializeOwned};
 use std::{
-    sync::LazyLock,
+    fmt::{self, Display},
+    fs::{self, File},
+    path::{Path, PathBuf},
+    sync::{
+        LazyLock,
+        atomic::{AtomicUsize, Ordering},
+    },
     time::{Duration, Instant},
 };
 
+/// [ExternalData::NAME] of every service, for validating config keys
+pub const SERVICE_NAMES: &[&str] = &[
+    weather::Weather::NAME,
+    weather::WeatherPoint::NAME,
+    transit::Transit::NAME,
+    transit::Schedules::NAME,
+    transit::Alerts::NAME,
+    transit::GtfsStatic::NAME,
+    bikes::Bikes::NAME,
+];
+
+/// Deserialize an ID from a string or a number. Most APIs use string IDs, but
+/// numbers are accepted for older configs and feeds
+fn deserialize_id<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Id {
+        String(String),
+        Number(u64),
+    }
+
+    Ok(match Id::deserialize(deserializer)? {
+        Id::String(id) => id,
+        Id::Number(id) => id.to_string(),
+    })
+}
+
+/// Maximum number of fetches that can be in flight at once, across all
+/// services. If we're at the limit, other fetches are deferred until the next
+/// data check.
+const MAX_CONCURRENT_FETCHES: usize = 2;
+
+/// Number of fetches currently in flight, across all services
+static FETCHES_IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+
+/// Maximum time for a whole request, including reading the response. A
+/// request that hangs would otherwise hold its fetch slot forever
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Maximum time to establish a connection
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
 /// Reqwest HTTP client
-static CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
-    reqwest::Client::builder()
+static CLIENT: LazyLock<reqwest::Client> =
+    LazyLock::new(|| client(Some(REQUEST_TIMEOUT)));
+
+/// Reqwest HTTP client for long-lived streams, which never complete. Streams
+/// detect dead connections themselves
+static STREAM_CLIENT: LazyLock<reqwest::Client> =
+    LazyLock::new(|| client(None));
+
+/// Build an HTTP client. Requests that take longer than the timeout fail
+fn client(timeout: Option<Duration>) -> reqwest::Client {
+    let mut builder = reqwest::Client::builder()
         .user_agent("gruber")
-        .build()
-        .unwrap()
-});
+        .connect_timeout(CONNECT_TIMEOUT);
+    if let Some(timeout) = timeout {
+        builder = builder.timeout(timeout);
+    }
+    builder.build().unwrap()
+}
 
 /// Trait for a container that fetches and stores data from an external API.
 /// This provides methods for fetching the data in a background task and
 /// storing it for a certain TTL before expiring and refetching it.
 pub trait ExternalData: 'static {
-    /// Minimum time between fetching data
-    const TTL: Duration;
-    type Data: 'static + Send;
+    /// Name of the service, for logging
+    const NAME: &'static str;
+    /// Minimum time between fetching data, if not overridden in the config
+    const DEFAULT_TTL: Duration;
+    /// Schedule for retrying after failed fetches
+    const RETRY_POLICY: RetryPolicy = RetryPolicy::DEFAULT;
+    /// Host of the API, if it reports a rate limit that should slow down
+    /// refreshes. See [http::RateLimit]
+    const RATE_LIMITED_HOST: Option<&'static str> = None;
+    type Data: 'static + Send + Serialize + DeserializeOwned;
+
+    /// Get the container of fetched data and fetch errors
+    fn store(&self) -> &DataStore<Self::Data>;
+
+    /// Get a mutable reference to the container of fetched data and errors
+    fn store_mut(&mut self) -> &mut DataStore<Self::Data>;
+
+    /// Pack the outcome of a fetch into a message so it can be passed from a
+    /// task back to the main thread
+    fn result_to_message(result: FetchResult<Self::Data>) -> Message;
+
+    /// Is this service used with the current config? Disabled services are
+    /// never fetched, and don't load cached data
+    fn is_enabled(&self) -> bool {
+        true
+    }
+
+    /// Is data being pushed to this service by a stream? If so, polling is
+    /// paused and the data is never outdated
+    fn is_streaming(&self) -> bool {
+        false
+    }
+
+    /// Are the settings of this service the same as another instance's? Used
+    /// to determine if a service needs to be rebuilt after a config reload
+    fn same_settings(&self, other: &Self) -> bool;
+
+    /// Identify the request that cached data came from, typically the URL.
+    /// If the key in the cache file doesn't match, e.g. because the config
+    /// changed, the cached data is discarded
+    fn cache_key(&self) -> String;
+
+    /// Replace this service with a new instance, built from a reloaded
+    /// config. If the settings haven't changed, the current instance is kept
+    /// so its data doesn't need to be refetched.
+    ///
+    /// If a fetch from the old instance is in flight, its result will still
+    /// be delivered to the new instance. It's dropped if it was fetched for a
+    /// different cache key, so data for the old settings is never shown.
+    fn reload(&mut self, mut new: Self)
+    where
+        Self: Sized,
+    {
+        if !self.same_settings(&new) {
+            info!("Settings for {} changed, rebuilding", Self::NAME);
+            new.load_cache();
+            *self = new;
+        }
+    }
+
+    /// Load the data saved by the most recent successful fetch, from a
+    /// previous run of the program. Cached data is always considered expired,
+    /// so it will be refetched as soon as possible. There's no versioning: if
+    /// the data format changed since the file was written, it fails to parse
+    /// and is ignored until the next fetch overwrites it.
+    fn load_cache(&mut self) {
+        let Some(path) = self.store().cache_path() else {
+            return;
+        };
+        if !self.is_enabled() {
+            return;
+        }
+        match CacheEntry::load(path) {
+            Ok(Some(entry)) if entry.key == self.cache_key() => {
+                info!(
+                    "Loaded cached {} data from {}",
+                    Self::NAME,
+                    entry.fetched_at
+                );
+                self.store_mut().data = Some(FetchedData {
+                    fetched_at: None,
+                    timestamp: entry.fetched_at,
+                    data: entry.data,
+                });
+            }
+            Ok(Some(_)) => {
+                info!(
+                    "Cached {} data is for old settings, ignoring",
+                    Self::NAME
+                );
+            }
+            Ok(None) => {}
+            Err(error) => {
+                warn!("Error loading cached {} data: {error:#}", Self::NAME);
+            }
+        }
+    }
+
+    /// Get the refresh interval that applies right now. This is the configured
+    /// interval, unless the API's rate limit is running low
+    fn ttl(&self) -> Duration {
+        self.throttle().unwrap_or_else(|| self.store().ttl())
+    }
+
+    /// If the API's remaining quota can't keep up with the configured
+    /// interval, get the stretched interval that will make it last until the
+    /// quota is refilled. The quota is split between all services polling the
+    /// same host
+    fn throttle(&self) -> Option<Duration> {
+        let host = Self::RATE_LIMITED_HOST?;
+        let interval = http::rate_limit(host)?
+            .min_interval(Utc::now(), http::pollers(host))?;
+        (interval > self.store().ttl()).then_some(interval)
+    }
+
+    /// Has it been so long since the last successful fetch that the data
+    /// shouldn't be trusted? This is the case if the data is older than the
+    /// given multiple of the refresh interval. Missing data isn't outdated,
+    /// and nothing is outdated if the multiple can't be represented (e.g.
+    /// it's negative or overflows)
+    fn is_outdated(&self, stale_multiplier: f64) -> bool {
+        let Ok(threshold) = Duration::try_from_secs_f64(
+            self.ttl().as_secs_f64() * stale_multiplier,
+        ) else {
+            return false;
+        };
+        !self.is_streaming()
+            && self
+                .store()
+                .last_success()
+                .is_some_and(|age| age > threshold)
+    }
 
     /// Get the current stored data, if any
-    fn data(&self) -> Option<&FetchedData<Self::Data>>;
+    fn data(&self) -> Option<&FetchedData<Self::Data>> {
+        self.store().data.as_ref()
+    }
 
-    /// Set the stored data with the output of a fetch
-    fn set_data(&mut self, data: FetchedData<Self::Data>);
+    /// Handle the outcome of a fetch task. The result is dropped if the data
+    /// is being streamed, or if the fetch was started under different
+    /// settings, e.g. by an instance that's since been replaced by a config
+    /// reload. Otherwise it's cached and stored with [Self::store_result]
+    fn handle_result(&mut self, result: FetchResult<Self::Data>) {
+        // A poll that was in flight when a stream connected is older than the
+        // streamed data, and would be the base for later streamed updates
+        if self.is_streaming() {
+            info!("Dropping polled {} result while streaming", Self::NAME);
+            self.store_mut().in_flight = false;
+            return;
+        }
+        if result.key != self.cache_key() {
+            info!(
+                "Dropping {} result fetched for old settings `{}`",
+                Self::NAME,
+                result.key
+            );
+            return;
+        }
+        if let Ok(data) = &result.result {
+            save_cache(self, data);
+        }
+        self.store_result(result.result);
+    }
 
-    /// Pack fetched data into a message so it can be passed from a task back
-    /// to the main thread
-    fn data_to_message(data: FetchedData<Self::Data>) -> Message;
+    /// Store the outcome of a fetch. On success the data is replaced and any
+    /// previous error is cleared. On failure the previous data is retained so
+    /// it can still be shown alongside the error.
+    /// After a failure, the next attempt is delayed according to the retry
+    /// policy.
+    fn store_result(
+        &mut self,
+        result: Result<FetchedData<Self::Data>, FetchError>,
+    ) {
+        let store = self.store_mut();
+        store.in_flight = false;
+        match result {
+            Ok(data) => {
+                store.data = Some(data);
+                store.error = None;
+                store.failures = 0;
+                store.retry_at = None;
+            }
+            Err(error) => {
+                store.failures += 1;
+                let delay = Self::RETRY_POLICY.delay(store.failures);
+                warn!(
+                    "Fetching {} failed {} time(s), retrying in {delay:?}: \
+                    {error}",
+                    Self::NAME,
+                    store.failures
+                );
+                store.error = Some(error);
+                store.retry_at = Some(Instant::now() + delay);
+            }
+        }
+    }
 
     /// If the stored data is missing or stale, create a task to refetch it.
-    /// Otherwise create an empty task that completes immediately
-    fn fetch_if_needed(&self) -> Task<Message> {
+    /// Otherwise create an empty task that completes immediately. A fetch is
+    /// also skipped if one is already in flight for this service, we're
+    /// backing off after a failure, or too many fetches are in flight overall.
+    fn fetch_if_needed(&mut self) -> Task<Message> {
+        // This runs for every service on each data check, so it keeps the
+        // count of services sharing each quota current
+        if let Some(host) = Self::RATE_LIMITED_HOST {
+            http::set_polling(
+                host,
+                Self::NAME,
+                self.is_enabled() && !self.is_streaming(),
+            );
+        }
+        let ttl = self.ttl();
+        let store = self.store();
         // If the data is missing or stale, refetch
-        if self.data().is_none_or(|data| data.is_expired(Self::TTL)) {
-            // TODO logging
-            Task::future(self.fetch()).and_then(move |data| {
-                Task::done(Self::data_to_message(FetchedData::new(data)))
-            })
-        } else {
-            // Data is fresh
-            Task::none()
+        let is_stale =
+            store.data.as_ref().is_none_or(|data| data.is_expired(ttl));
+        let is_backing_off = store
+            .retry_at
+            .is_some_and(|retry_at| retry_at > Instant::now());
+        if !is_stale
+            || store.in_flight
+            || is_backing_off
+            || self.is_streaming()
+            || !self.is_enabled()
+        {
+            return Task::none();
         }
+        let Some(permit) = FetchPermit::acquire() else {
+            // Too many fetches already in flight; try again next check
+            return Task::none();
+        };
+
+        let future = self.fetch();
+        let cache_key = self.cache_key();
+        self.store_mut().in_flight = true;
+        Task::perform(
+            async move {
+                // Hold the permit until the request is done
+                let _permit = permit;
+                FetchResult {
+                    key: cache_key,
+                    result: future
+                        .await
+                        .map(FetchedData::new)
+                        .map_err(FetchError::new),
+                }
+            },
+            Self::result_to_message,
+        )
     }
 
     /// Fetch new data from the external source
@@ -58,23 +351,459 @@ pub trait ExternalData: 'static {
     ) -> impl 'static + Future<Output = anyhow::Result<Self::Data>> + Send;
 }
 
+/// Save a service's freshly fetched data to its cache file, if caching is
+/// enabled. Errors are logged, since the data is still usable without a cache
+fn save_cache<S: ExternalData + ?Sized>(
+    service: &S,
+    data: &FetchedData<S::Data>,
+) {
+    let Some(path) = service.store().cache_path() else {
+        return;
+    };
+    if let Err(error) = data.save(path, service.cache_key()) {
+        warn!(
+            "Error caching {} data to `{}`: {error:#}",
+            S::NAME,
+            path.display()
+        );
+    }
+}
+
+/// Outcome of a single fetch, passed from the fetch task back to the service
+#[derive(Debug)]
+pub struct FetchResult<T> {
+    /// [ExternalData::cache_key] of the service when the fetch started, to
+    /// detect results for settings that have since changed
+    key: String,
+    result: Result<FetchedData<T>, FetchError>,
+}
+
+/// Storage for a single service's external data. Holds the most recent
+/// successful fetch, as well as the error from the most recent fetch if it
+/// failed.
+#[derive(Debug)]
+pub struct DataStore<T> {
+    data: Option<FetchedData<T>>,
+    error: Option<FetchError>,
+    /// Is there a fetch currently running for this service?
+    in_flight: bool,
+    /// Number of consecutive failed fetches
+    failures: u32,
+    /// After a failure, don't fetch again until this time
+    retry_at: Option<Instant>,
+    /// How often to refetch the data
+    refresh: RefreshSchedule,
+    /// File to save the most recent data to, so it can be shown immediately
+    /// after a restart. `None` if caching is disabled
+    cache_path: Option<PathBuf>,
+}
+
+impl<T> DataStore<T> {
+    /// Create an empty store that refreshes its data on the given schedule.
+    /// Caching is disabled when running against mock or replayed data, so
+    /// fake data doesn't leak into real runs.
+    pub fn new(refresh: RefreshSchedule, cache_path: Option<PathBuf>) -> Self {
+        Self {
+            data: None,
+            error: None,
+            in_flight: false,
+            failures: 0,
+            retry_at: None,
+            refresh,
+            cache_path: cache_path.filter(|_| Backend::get().is_live()),
+        }
+    }
+
+    fn cache_path(&self) -> Option<&Path> {
+        self.cache_path.as_deref()
+    }
+
+    /// Modify the data in place, e.g. to apply a streamed update, and mark it
+    /// as freshly fetched. Does nothing if there's no data yet
+    fn update(&mut self, f: impl FnOnce(&mut T)) {
+        if let Some(data) = &mut self.data {
+            f(&mut data.data);
+            data.fetched_at = Some(Instant::now());
+            data.timestamp = Utc::now();
+        }
+    }
+
+    /// Get the error from the most recent fetch. `None` if the most recent
+    /// fetch succeeded, or there hasn't been a fetch yet
+    pub fn error(&self) -> Option<&FetchError> {
+        self.error.as_ref()
+    }
+
+    /// Get the time elapsed since the last successful fetch. `None` if there
+    /// hasn't been a successful fetch yet
+    pub fn last_success(&self) -> Option<Duration> {
+        self.data.as_ref().map(FetchedData::age)
+    }
+
+    /// Get the configured refresh interval that applies right now
+    fn ttl(&self) -> Duration {
+        self.refresh.ttl(Local::now().time())
+    }
+
+    /// If the current data was loaded from the cache rather than fetched
+    /// during this run, get the time it was originally fetched
+    pub fn cached_since(&self) -> Option<DateTime<Utc>> {
+        self.data
+            .as_ref()
+            .filter(|data| data.fetched_at.is_none())
+            .map(|data| data.timestamp)
+    }
+}
+
+/// Schedule for retrying failed fetches. The delay doubles after each
+/// consecutive failure, up to a maximum. Jitter is applied to each delay so
+/// retries don't synchronize.
+#[derive(Copy, Clone, Debug)]
+pub struct RetryPolicy {
+    /// Delay after the first failure
+    pub initial: Duration,
+    /// Upper bound on the delay, before jitter
+    pub max: Duration,
+}
+
+impl RetryPolicy {
+    pub const DEFAULT: Self = Self {
+        initial: Duration::from_secs(5),
+        max: Duration::from_secs(5 * 60),
+    };
+
+    /// Get the delay before the next attempt, given the number of consecutive
+    /// failures so far (including the one that just happened)
+    fn delay(&self, failures: u32) -> Duration {
+        let base = self.base_delay(failures);
+        // Jitter within [base/2, base]
+        base.mul_f64(rand::random_range(0.5..=1.0))
+    }
+
+    /// Get the un-jittered delay for the given number of consecutive failures
+    fn base_delay(&self, failures: u32) -> Duration {
+        let exponent = failures.saturating_sub(1).min(16);
+        self.initial
+            .saturating_mul(2u32.pow(exponent))
+            .min(self.max)
+    }
+}
+
+/// A slot in the global limit of concurrent fetches. The slot is released when
+/// the permit is dropped.
+struct FetchPermit;
+
+impl FetchPermit {
+    /// Claim a fetch slot. Return `None` if all slots are taken
+    fn acquire() -> Option<Self> {
+        FETCHES_IN_FLIGHT
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+                (count < MAX_CONCURRENT_FETCHES).then_some(count + 1)
+            })
+            .ok()
+            .map(|_| Self)
+    }
+}
+
+impl Drop for FetchPermit {
+    fn drop(&mut self) {
+        FETCHES_IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
 /// Container for data fetched externally. Includes a timestamp of when it was
 /// fetched
 #[derive(Debug)]
 pub struct FetchedData<T> {
-    fetched_at: Instant,
+    /// Monotonic fetch time, for expiration. `None` if the data was loaded from
+    /// the cache, since instants aren't meaningful across runs
+    fetched_at: Option<Instant>,
+    /// Wall clock fetch time. This can be persisted, but may jump if the
+    /// system clock changes
+    timestamp: DateTime<Utc>,
     data: T,
 }
 
 impl<T> FetchedData<T> {
     fn new(data: T) -> Self {
         Self {
-            fetched_at: Instant::now(),
+            fetched_at: Some(Instant::now()),
+            timestamp: Utc::now(),
             data,
         }
     }
 
+    /// Time since the data was fetched
+    fn age(&self) -> Duration {
+        match self.fetched_at {
+            Some(fetched_at) => fetched_at.elapsed(),
+            None => (Utc::now() - self.timestamp).to_std().unwrap_or_default(),
+        }
+    }
+
     fn is_expired(&self, ttl: Duration) -> bool {
-        self.fetched_at + ttl < Instant::now()
+        // Cached data is always expired
+        self.fetched_at
+            .is_none_or(|fetched_at| fetched_at + ttl < Instant::now())
+    }
+}
+
+/// Format of a cache file, containing the data from a service's most recent
+/// successful fetch
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry<T> {
+    /// See [ExternalData::cache_key]
+    key: String,
+    fetched_at: DateTime<Utc>,
+    data: T,
+}
+
+impl<T: DeserializeOwned> CacheEntry<T> {
+    /// Load a cache file. Return `None` if it doesn't exist
+    fn load(path: &Path) -> anyhow::Result<Option<Self>> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(None);
+            }
+            Err(error) => return Err(error.into()),
+        };
+        serde_json::from_reader(file)
+            .map(Some)
+            .context(format!("Error parsing cache file `{}`", path.display()))
+    }
+}
+
+impl<T: Serialize> FetchedData<T> {
+    /// Save to a cache file. The file is written to a temporary path then moved
+    /// into place, so a power loss mid-write can't corrupt it
+    fn save(&self, path: &Path, key: String) -> anyhow::Result<()> {
+        let entry = CacheEntry {
+            key,
+            fetched_at: self.timestamp,
+            data: &self.data,
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let temp_path = path.with_extension("json.tmp");
+        serde_json::to_writer(File::create(&temp_path)?, &entry)?;
+        fs::rename(&temp_path, path)?;
+        Ok(())
+    }
+}
+
+/// An error that occurred while fetching external data. The error chain is
+/// flattened into a string at creation, so it can be stored and displayed.
+#[derive(Debug)]
+pub struct FetchError {
+    /// Full error chain, formatted as `outer: inner: ...`
+    message: String,
+}
+
+impl FetchError {
+    fn new(error: anyhow::Error) -> Self {
+        Self {
+            message: format!("{error:#}"),
+        }
+    }
+}
+
+impl Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, net::TcpListener};
+    use tempfile::TempDir;
+
+    /// Minimal service to test the shared fetch logic with
+    #[derive(Debug)]
+    struct TestService {
+        url: String,
+        store: DataStore<Vec<i32>>,
+    }
+
+    impl TestService {
+        fn new(url: &str, cache_path: Option<PathBuf>) -> Self {
+            Self {
+                url: url.to_owned(),
+                store: DataStore::new(
+                    RefreshSchedule::fixed(Self::DEFAULT_TTL),
+                    cache_path,
+                ),
+            }
+        }
+
+        /// Build the result of a fetch started for the given URL
+        fn result(
+            url: &str,
+            result: anyhow::Result<Vec<i32>>,
+        ) -> FetchResult<Vec<i32>> {
+            FetchResult {
+                key: url.into(),
+                result: result.map(FetchedData::new).map_err(FetchError::new),
+            }
+        }
+    }
+
+    impl ExternalData for TestService {
+        const NAME: &'static str = "test";
+        const DEFAULT_TTL: Duration = Duration::from_secs(60);
+        type Data = Vec<i32>;
+
+        fn store(&self) -> &DataStore<Self::Data> {
+            &self.store
+        }
+
+        fn store_mut(&mut self) -> &mut DataStore<Self::Data> {
+            &mut self.store
+        }
+
+        fn same_settings(&self, other: &Self) -> bool {
+            self.url == other.url
+        }
+
+        fn cache_key(&self) -> String {
+            self.url.clone()
+        }
+
+        fn result_to_message(_: FetchResult<Self::Data>) -> Message {
+            unreachable!("Test service is never fetched")
+        }
+
+        fn fetch(
+            &self,
+        ) -> impl 'static + Future<Output = anyhow::Result<Self::Data>> + Send
+        {
+            // Tests feed results in directly instead
+            std::future::pending()
+        }
+    }
+
+    /// A failed fetch should be shown alongside the last good data
+    #[test]
+    fn test_store_error() {
+        let mut service = TestService::new("a", None);
+        service.handle_result(TestService::result("a", Ok(vec![1, 2, 3])));
+        service.handle_result(TestService::result(
+            "a",
+            Err(anyhow::anyhow!("Server error")),
+        ));
+
+        assert_eq!(service.data().unwrap().data, vec![1, 2, 3]);
+        assert_eq!(
+            service.store().error().unwrap().to_string(),
+            "Server error"
+        );
+        assert_eq!(service.store.failures, 1);
+
+        // The next success clears the error
+        service.handle_result(TestService::result("a", Ok(vec![4])));
+        assert_eq!(service.data().unwrap().data, vec![4]);
+        assert!(service.store().error().is_none());
+    }
+
+    /// A request that never completes should time out, so it frees up its
+    /// fetch slot and the service backs off and retries
+    #[test]
+    fn test_fetch_timeout() {
+        // Accepts connections, but never responds
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let request = client(Some(Duration::from_millis(100))).get(&url);
+        let error = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(async { request.send().await })
+            .unwrap_err();
+        assert!(error.is_timeout(), "{error}");
+
+        let mut service = TestService::new(&url, None);
+        service.store.in_flight = true;
+        service.handle_result(TestService::result(&url, Err(error.into())));
+        assert!(!service.store.in_flight);
+        assert_eq!(service.store.failures, 1);
+        assert!(service.store.retry_at.is_some_and(|retry_at| {
+            retry_at > Instant::now()
+                && retry_at
+                    <= Instant::now() + TestService::RETRY_POLICY.initial
+        }));
+    }
+
+    /// A fetch started before a reload shouldn't be shown or cached under the
+    /// new settings
+    #[test]
+    fn test_reload_drops_old_result() {
+        let cache_dir = TempDir::new().unwrap();
+        let cache_path = cache_dir.path().join("test.json");
+        let mut service = TestService::new("old", Some(cache_path.clone()));
+        service.store.in_flight = true;
+        service.reload(TestService::new("new", Some(cache_path.clone())));
+
+        service.handle_result(TestService::result("old", Ok(vec![1])));
+        assert!(service.data().is_none());
+        assert!(!cache_path.exists());
+
+        service.handle_result(TestService::result("new", Ok(vec![2])));
+        assert_eq!(service.data().unwrap().data, vec![2]);
+        let entry: CacheEntry<Vec<i32>> =
+            CacheEntry::load(&cache_path).unwrap().unwrap();
+        assert_eq!(entry.key, "new");
+        assert_eq!(entry.data, vec![2]);
+    }
+
+    /// A bad multiplier should never panic, since this runs on every render
+    #[test]
+    fn test_is_outdated_invalid_multiplier() {
+        let mut service = TestService::new("a", None);
+        service.handle_result(TestService::result("a", Ok(vec![1])));
+
+        assert!(!service.is_outdated(3.0));
+        for multiplier in [-1.0, f64::NAN, f64::INFINITY, 1e300] {
+            assert!(!service.is_outdated(multiplier), "{multiplier}");
+        }
+    }
+
+    #[test]
+    fn test_cache_round_trip() {
+        let path = env::temp_dir()
+            .join(format!("gruber-cache-{}", std::process::id()))
+            .join("test.json");
+        let data = FetchedData::new(vec![1, 2, 3]);
+        data.save(&path, "key".into()).unwrap();
+
+        let entry: CacheEntry<Vec<i32>> =
+            CacheEntry::load(&path).unwrap().unwrap();
+        assert_eq!(entry.key, "key");
+        assert_eq!(entry.fetched_at, data.timestamp);
+        assert_eq!(entry.data, vec![1, 2, 3]);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        assert!(CacheEntry::<Vec<i32>>::load(&path).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let policy = RetryPolicy {
+            initial: Duration::from_secs(5),
+            max: Duration::from_secs(60),
+        };
+        assert_eq!(policy.base_delay(1), Duration::from_secs(5));
+        assert_eq!(policy.base_delay(2), Duration::from_secs(10));
+        assert_eq!(policy.base_delay(4), Duration::from_secs(40));
+        assert_eq!(policy.base_delay(5), Duration::from_secs(60));
+        assert_eq!(policy.base_delay(1000), Duration::from_secs(60));
+
+        for failures in 1..10 {
+            let base = policy.base_delay(failures);
+            let delay = policy.delay(failures);
+            assert!(delay >= base / 2 && delay <= base, "{delay:?}");
+        }
     }
 }
@@ -0,0 +1,338 @@
+//! Bike share availability from a GBFS feed, e.g. Bluebikes.
+//! <https://gbfs.org/specification/reference/>
+
+use crate::{
+    Message,
+    config::Config,
+    services::{
+        CLIENT, DataStore, ExternalData, FetchResult, deserialize_id,
+        http::fetch_json,
+    },
+};
+use anyhow::Context;
+use itertools::Itertools;
+use log::{info, warn};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::time::Duration;
+
+/// Bike and dock availability at the configured stations. Disabled if no bike
+/// share is configured
+#[derive(Debug)]
+pub struct Bikes {
+    config: Option<BikeShareConfig>,
+    store: DataStore<BikeData>,
+}
+
+impl Bikes {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            config: config.bike_share.clone(),
+            store: DataStore::new(
+                config.refresh.schedule::<Self>(),
+                config.cache_path::<Self>(),
+            ),
+        }
+    }
+
+    /// Get availability at each configured station that's in the feed, in
+    /// the order they're configured. `None` if data hasn't been loaded yet
+    pub fn stations(&self) -> Option<Vec<StationAvailability>> {
+        let config = self.config.as_ref()?;
+        let data = &self.data()?.data;
+        let stations = config
+            .stations
+            .iter()
+            .filter_map(|station| {
+                let info = data
+                    .information
+                    .iter()
+                    .find(|info| info.station_id == station.id)?;
+                let status = data
+                    .status
+                    .iter()
+                    .find(|status| status.station_id == station.id)?;
+                Some(StationAvailability {
+                    name: station.name.clone().unwrap_or(info.name.clone()),
+                    bikes: status
+                        .num_bikes_available
+                        .saturating_sub(status.num_ebikes_available),
+                    ebikes: status.num_ebikes_available,
+                    docks: status.num_docks_available,
+                    is_open: status.is_installed
+                        && status.is_renting
+                        && status.is_returning,
+                })
+            })
+            .collect();
+        Some(stations)
+    }
+}
+
+impl ExternalData for Bikes {
+    const NAME: &'static str = "bikes";
+    // GBFS feeds typically have a TTL of a minute or less
+    const DEFAULT_TTL: Duration = Duration::from_secs(60);
+    type Data = BikeData;
+
+    fn same_settings(&self, other: &Self) -> bool {
+        self.config == other.config && self.store.refresh == other.store.refresh
+    }
+
+    fn cache_key(&self) -> String {
+        // Stations that aren't configured are dropped from the data, so adding
+        // one requires a fresh fetch
+        self.config
+            .as_ref()
+            .map(|config| {
+                format!(
+                    "{}#{}#{}",
+                    config.station_information_url,
+                    config.station_status_url,
+                    config
+                        .stations
+                        .iter()
+                        .map(|station| &station.id)
+                        .format(",")
+                )
+            })
+            .unwrap_or_default()
+    }
+
+    fn store(&self) -> &DataStore<Self::Data> {
+        &self.store
+    }
+
+    fn store_mut(&mut self) -> &mut DataStore<Self::Data> {
+        &mut self.store
+    }
+
+    fn result_to_message(result: FetchResult<Self::Data>) -> Message {
+        Message::BikesFetched(result)
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.config.is_some()
+    }
+
+    fn fetch(
+        &self,
+    ) -> impl 'static + Future<Output = anyhow::Result<Self::Data>> + Send {
+        let config = self.config.clone().unwrap_or_default();
+        info!(
+            "Fetching bike share data from {} and {}",
+            config.station_information_url, config.station_status_url
+        );
+        let information_request = CLIENT.get(&config.station_information_url);
+        let status_request = CLIENT.get(&config.station_status_url);
+        async move {
+            let information: GbfsResponse<StationInformation> =
+                fetch_json("bike_stations", information_request)
+                    .await
+                    .context("Error fetching bike station information")?;
+            let status: GbfsResponse<StationStatus> =
+                fetch_json("bike_status", status_request)
+                    .await
+                    .context("Error fetching bike station status")?;
+            Ok(BikeData::new(&config, information, status))
+        }
+    }
+}
+
+/// Bike share system to show availability for
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+pub struct BikeShareConfig {
+    /// URL of the system's `station_information.json` feed
+    pub station_information_url: String,
+    /// URL of the system's `station_status.json` feed
+    pub station_status_url: String,
+    pub stations: Vec<BikeStation>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct BikeStation {
+    /// Display name. Defaults to the station's name in the feed
+    pub name: Option<String>,
+    /// GBFS station ID
+    #[serde(deserialize_with = "deserialize_id")]
+    pub id: String,
+}
+
+/// Station information and status for the configured stations. Feeds cover
+/// the whole system, so everything else is dropped
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BikeData {
+    information: Vec<StationInformation>,
+    status: Vec<StationStatus>,
+}
+
+impl BikeData {
+    fn new(
+        config: &BikeShareConfig,
+        information: GbfsResponse<StationInformation>,
+        status: GbfsResponse<StationStatus>,
+    ) -> Self {
+        let is_configured =
+            |id: &str| config.stations.iter().any(|station| station.id == id);
+        let information: Vec<_> = information
+            .data
+            .stations
+            .into_iter()
+            .filter(|info| is_configured(&info.station_id))
+            .collect();
+        for station in &config.stations {
+            if !information.iter().any(|info| info.station_id == station.id) {
+                warn!("Bike station `{}` is not in the feed", station.id);
+            }
+        }
+        Self {
+            information,
+            status: status
+                .data
+                .stations
+                .into_iter()
+                .filter(|status| is_configured(&status.station_id))
+                .collect(),
+        }
+    }
+}
+
+/// Availability at one station, ready to display
+#[derive(Clone, Debug, PartialEq)]
+pub struct StationAvailability {
+    pub name: String,
+    /// Classic (non-electric) bikes available
+    pub bikes: u32,
+    pub ebikes: u32,
+    pub docks: u32,
+    /// Is the station renting and accepting returns?
+    pub is_open: bool,
+}
+
+impl StationAvailability {
+    /// No bikes of either kind to take
+    pub fn is_empty(&self) -> bool {
+        self.bikes == 0 && self.ebikes == 0
+    }
+
+    /// No docks to return a bike to
+    pub fn is_full(&self) -> bool {
+        self.docks == 0
+    }
+}
+
+/// Wrapper around every GBFS feed
+#[derive(Debug, Deserialize)]
+struct GbfsResponse<T> {
+    data: GbfsStations<T>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GbfsStations<T> {
+    stations: Vec<T>,
+}
+
+/// An entry in `station_information.json`. Unused fields are ignored
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct StationInformation {
+    #[serde(deserialize_with = "deserialize_id")]
+    station_id: String,
+    name: String,
+}
+
+/// An entry in `station_status.json`. Unused fields are ignored
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct StationStatus {
+    #[serde(deserialize_with = "deserialize_id")]
+    station_id: String,
+    /// Bikes of all kinds, including e-bikes
+    num_bikes_available: u32,
+    /// Not part of the spec before v2.1, but published by most systems
+    #[serde(default)]
+    num_ebikes_available: u32,
+    num_docks_available: u32,
+    #[serde(deserialize_with = "deserialize_flag")]
+    is_installed: bool,
+    #[serde(deserialize_with = "deserialize_flag")]
+    is_renting: bool,
+    #[serde(deserialize_with = "deserialize_flag")]
+    is_returning: bool,
+}
+
+/// GBFS v1 uses 0 and 1 for booleans, while later versions use `true` and
+/// `false`
+fn deserialize_flag<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<bool, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Flag {
+        Bool(bool),
+        Int(u8),
+    }
+
+    Ok(match Flag::deserialize(deserializer)? {
+        Flag::Bool(flag) => flag,
+        Flag::Int(flag) => flag != 0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::{
+        FetchedData,
+        test_util::{config, load_fixture},
+    };
+    use serde_json::json;
+
+    #[test]
+    fn test_stations() {
+        let config = config(json!({
+            "bike_share": {
+                "station_information_url": "https://example.com/info.json",
+                "station_status_url": "https://example.com/status.json",
+                "stations": [
+                    {"id": "A32019"},
+                    {"name": "Home", "id": 67},
+                    {"id": "missing"},
+                    {"id": "A32001"},
+                ],
+            },
+        }));
+        let data = BikeData::new(
+            config.bike_share.as_ref().unwrap(),
+            load_fixture("bike_stations.json"),
+            load_fixture("bike_status.json"),
+        );
+        let mut bikes = Bikes::new(&config);
+        bikes.store_result(Ok(FetchedData::new(data)));
+
+        // Missing stations are dropped, and the rest keep the config order
+        assert_eq!(
+            bikes.stations().unwrap(),
+            vec![
+                StationAvailability {
+                    name: "Central Square at Mass Ave / Essex St".into(),
+                    bikes: 0,
+                    ebikes: 0,
+                    docks: 19,
+                    is_open: true,
+                },
+                StationAvailability {
+                    name: "Home".into(),
+                    bikes: 5,
+                    ebikes: 2,
+                    docks: 0,
+                    is_open: true,
+                },
+                StationAvailability {
+                    name: "Kendall T".into(),
+                    bikes: 3,
+                    ebikes: 1,
+                    docks: 8,
+                    is_open: false,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,659 @@
+use anyhow::Context;
+use chrono::{DateTime, FixedOffset, Local, SecondsFormat, TimeDelta, Utc};
+use log::info;
+use reqwest::{
+    StatusCode,
+    header::{HeaderMap, HeaderName, HeaderValue},
+};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::{self, File},
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+/// Services currently polling each rate-limited API host. They share the
+/// host's quota, so each one gets an equal part of it
+static POLLERS: LazyLock<Mutex<HashMap<&str, HashSet<&str>>>> =
+    LazyLock::new(Default::default);
+
+/// Where HTTP responses come from. Set once at startup
+static BACKEND: OnceLock<Backend> = OnceLock::new();
+
+/// Most recent rate limit reported by each API host
+static RATE_LIMITS: LazyLock<Mutex<HashMap<String, RateLimit>>> =
+    LazyLock::new(Default::default);
+
+/// Source of HTTP responses for all services
+#[derive(Debug)]
+pub enum Backend {
+    /// Send real requests
+    Live,
+    /// Don't send any requests. Instead, load a single fixture for each
+    /// request from `<dir>/<name>.json`
+    Mock(PathBuf),
+    /// Send real requests, and save each response to
+    /// `<dir>/<name>/<timestamp>.json`
+    Record(PathBuf),
+    /// Don't send any requests. Instead, replay responses saved by
+    /// [Backend::Record] in order. Once the recordings for a request are
+    /// exhausted, the last one is repeated
+    Replay(PathBuf),
+}
+
+impl Backend {
+    /// Set the backend for the lifetime of the program. If this isn't called,
+    /// the live backend is used
+    pub fn set(self) {
+        info!("Using HTTP backend {self:?}");
+        BACKEND.set(self).expect("HTTP backend set twice");
+    }
+
+    pub fn get() -> &'static Self {
+        BACKEND.get().unwrap_or(&Self::Live)
+    }
+
+    /// Are responses coming from the real APIs?
+    pub fn is_live(&self) -> bool {
+        matches!(self, Self::Live | Self::Record(_))
+    }
+}
+
+/// Send a request and parse the response body as JSON. The name identifies
+/// the request when loading or saving recordings, and should be unique to the
+/// service and endpoint. Each response is logged with its status code, size,
+/// and duration.
+pub async fn fetch_json<T: DeserializeOwned>(
+    name: &str,
+    request: reqwest::RequestBuilder,
+) -> anyhow::Result<T> {
+    fetch(name, request).await?.json()
+}
+
+/// Send a request through the configured backend and return the response.
+/// Error status codes are returned as errors.
+pub async fn fetch(
+    name: &str,
+    request: reqwest::RequestBuilder,
+) -> anyhow::Result<HttpResponse> {
+    let response = match Backend::get() {
+        Backend::Live => HttpResponse::send(request).await?,
+        Backend::Mock(dir) => {
+            Recording::load(&dir.join(format!("{name}.json")))?.into_response()
+        }
+        Backend::Record(dir) => {
+            let response = HttpResponse::send(request).await?;
+            Recording::new(&response).save(&dir.join(name))?;
+            response
+        }
+        Backend::Replay(dir) => {
+            let response = Recording::load(&next_replay(&dir.join(name))?)?
+                .into_response();
+            // Reproduce the original timing, to catch issues with slow
+            // responses
+            tokio::time::sleep(response.duration).await;
+            response
+        }
+    };
+
+    info!(
+        "GET {} {} ({} bytes) in {:?}",
+        response.url,
+        response.status,
+        response.body.len(),
+        response.duration
+    );
+    // Track the quota even on error responses, since that's when it matters
+    if let Some(rate_limit) = RateLimit::from_headers(&response.headers) {
+        if let Some(host) = reqwest::Url::parse(&response.url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_owned))
+        {
+            RATE_LIMITS
+                .lock()
+                .unwrap_or_else(|error| error.into_inner())
+                .insert(host, rate_limit);
+        }
+    }
+    if response.status.is_client_error() || response.status.is_server_error() {
+        anyhow::bail!("HTTP status {} from {}", response.status, response.url);
+    }
+    Ok(response)
+}
+
+/// Get the most recent rate limit reported by an API host, e.g.
+/// `api-v3.mbta.com`. `None` if the host hasn't reported one
+pub fn rate_limit(host: &str) -> Option<RateLimit> {
+    RATE_LIMITS
+        .lock()
+        .unwrap_or_else(|error| error.into_inner())
+        .get(host)
+        .copied()
+}
+
+/// Record whether a service is polling a rate-limited API host, so the host's
+/// quota can be split between all the services using it
+pub fn set_polling(host: &'static str, service: &'static str, polling: bool) {
+    let mut pollers = POLLERS.lock().unwrap_or_else(|error| error.into_inner());
+    let services = pollers.entry(host).or_default();
+    if polling {
+        services.insert(service);
+    } else {
+        services.remove(service);
+    }
+}
+
+/// Get the number of services polling an API host. Always at least 1, since
+/// whoever is asking is about to make a request
+pub fn pollers(host: &str) -> u32 {
+    let pollers = POLLERS.lock().unwrap_or_else(|error| error.into_inner());
+    let count = pollers.get(host).map_or(0, HashSet::len);
+    u32::try_from(count).unwrap_or(u32::MAX).max(1)
+}
+
+/// Get the path of the next recording to replay from a directory. Recordings
+/// are replayed in lexical order, which is chronological for recorded files
+fn next_replay(dir: &Path) -> anyhow::Result<PathBuf> {
+    /// Number of recordings replayed so far for each directory
+    static POSITIONS: LazyLock<Mutex<HashMap<PathBuf, usize>>> =
+        LazyLock::new(Default::default);
+
+    let mut paths = fs::read_dir(dir)
+        .context(format!("Error reading recordings in `{}`", dir.display()))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    paths.retain(|path| path.extension().is_some_and(|ext| ext == "json"));
+    paths.sort();
+    let mut positions =
+        POSITIONS.lock().unwrap_or_else(|error| error.into_inner());
+    let position = positions.entry(dir.to_owned()).or_default();
+    let path = paths
+        .get(*position)
+        .or(paths.last())
+        .cloned()
+        .context(format!("No recordings in `{}`", dir.display()))?;
+    *position += 1;
+    Ok(path)
+}
+
+/// An HTTP response, received live or loaded from a recording
+#[derive(Debug)]
+pub struct HttpResponse {
+    pub url: String,
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    /// Time from sending the request to receiving the full body
+    pub duration: Duration,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    /// Send a request over the network
+    async fn send(request: reqwest::RequestBuilder) -> anyhow::Result<Self> {
+        let start = Instant::now();
+        let response = request.send().await.context("Error sending request")?;
+        let url = response.url().to_string();
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = response
+            .bytes()
+            .await
+            .context("Error reading response body")?;
+        Ok(Self {
+            url,
+            status,
+            headers,
+            duration: start.elapsed(),
+            body: body.into(),
+        })
+    }
+
+    /// Parse the body as JSON
+    pub fn json<T: DeserializeOwned>(&self) -> anyhow::Result<T> {
+        serde_json::from_slice(&self.body)
+            .context("Error parsing response body")
+    }
+}
+
+/// Request quota reported by an API in the `x-ratelimit-*` response headers.
+/// The quota is refilled at the end of each window.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RateLimit {
+    /// Requests left in the current window
+    pub remaining: u32,
+    /// When the current window ends
+    pub reset: DateTime<Utc>,
+    /// When the limit was reported
+    pub reported_at: DateTime<Utc>,
+}
+
+impl RateLimit {
+    const REMAINING_HEADER: &str = "x-ratelimit-remaining";
+    /// Unix timestamp, in seconds, of the end of the window
+    const RESET_HEADER: &str = "x-ratelimit-reset";
+
+    /// Parse the rate limit from response headers. `None` if either header is
+    /// missing or invalid
+    fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let header = |name: &str| headers.get(name)?.to_str().ok();
+        let remaining = header(Self::REMAINING_HEADER)?.parse().ok()?;
+        let reset = DateTime::from_timestamp(
+            header(Self::RESET_HEADER)?.parse().ok()?,
+            0,
+        )?;
+        Some(Self {
+            remaining,
+            reset,
+            reported_at: Utc::now(),
+        })
+    }
+
+    /// Get the minimum time between requests needed for each of `pollers`
+    /// services to spread its share of the remaining quota evenly over the
+    /// rest of the window. If there isn't at least one request left for each,
+    /// this is the rest of the window. `None` if the window has already ended,
+    /// so the limit no longer applies
+    pub fn min_interval(
+        &self,
+        now: DateTime<Utc>,
+        pollers: u32,
+    ) -> Option<Duration> {
+        if self.reset <= now {
+            return None;
+        }
+        let window = (self.reset - self.reported_at).to_std().ok()?;
+        Some(window / (self.remaining / pollers.max(1)).max(1))
+    }
+}
+
+/// Incremental parser for a `text/event-stream` response body. Feed it chunks
+/// as they arrive, and it returns each event once it's complete.
+#[derive(Debug, Default)]
+pub struct EventStreamParser {
+    /// Received bytes that don't make up a complete event yet
+    buffer: Vec<u8>,
+}
+
+impl EventStreamParser {
+    /// Add a chunk of the body, and return any events it completes. Comments
+    /// (e.g. keep-alives) and events without data are dropped
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<ServerSentEvent> {
+        // Line endings can be \r\n. Dropping all \r is simpler than tracking
+        // pairs split across chunks, and \r never appears in JSON data
+        self.buffer
+            .extend(chunk.iter().filter(|&&byte| byte != b'\r'));
+        let mut events = Vec::new();
+        // Events are separated by a blank line
+        while let Some(end) =
+            self.buffer.windows(2).position(|window| window == b"\n\n")
+        {
+            let block: Vec<u8> = self.buffer.drain(..end + 2).collect();
+            events.extend(ServerSentEvent::parse(&String::from_utf8_lossy(
+                &block,
+            )));
+        }
+        events
+    }
+}
+
+/// A single event from a `text/event-stream` response
+#[derive(Debug, PartialEq)]
+pub struct ServerSentEvent {
+    /// Event type, e.g. `reset`. Defaults to `message`
+    pub event: String,
+    /// Data lines, joined with newlines
+    pub data: String,
+}
+
+impl ServerSentEvent {
+    /// Parse the lines of a single event. `None` if it has no data
+    fn parse(block: &str) -> Option<Self> {
+        let mut event = None;
+        let mut data: Option<String> = None;
+        for line in block.lines() {
+            let (field, value) = line.split_once(':').unwrap_or((line, ""));
+            let value = value.strip_prefix(' ').unwrap_or(value);
+            match field {
+                "event" => event = Some(value.to_owned()),
+                "data" => match &mut data {
+                    Some(data) => {
+                        data.push('\n');
+                        data.push_str(value);
+                    }
+                    None => data = Some(value.to_owned()),
+                },
+                // Empty field is a comment. Other fields (id, retry) aren't
+                // needed
+                _ => {}
+            }
+        }
+        Some(Self {
+            event: event.unwrap_or_else(|| "message".to_owned()),
+            data: data?,
+        })
+    }
+}
+
+/// A response saved to disk. When loaded, all timestamps in the body are
+/// shifted by the time elapsed since `recorded_at`, so the data always looks
+/// current. Everything but the timestamp and body is optional, so fixtures
+/// can be written by hand.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Recording {
+    #[serde(default)]
+    url: String,
+    /// When the response was originally received
+    recorded_at: DateTime<FixedOffset>,
+    #[serde(default = "Recording::default_status")]
+    status: u16,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    /// Time taken to receive the response, in milliseconds
+    #[serde(default)]
+    duration_ms: u64,
+    /// If true, `body` is a string containing a response body that wasn't
+    /// valid JSON, such as an HTML error page
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    text: bool,
+    body: Value,
+}
+
+impl Recording {
+    fn default_status() -> u16 {
+        200
+    }
+
+    /// Capture a live response
+    fn new(response: &HttpResponse) -> Self {
+        let (text, body) = match serde_json::from_slice(&response.body) {
+            Ok(body) => (false, body),
+            Err(_) => (
+                true,
+                Value::String(
+                    String::from_utf8_lossy(&response.body).into_owned(),
+                ),
+            ),
+        };
+        Self {
+            url: response.url.clone(),
+            recorded_at: Local::now().fixed_offset(),
+            status: response.status.as_u16(),
+            headers: response
+                .headers
+                .iter()
+                .filter_map(|(name, value)| {
+                    Some((name.to_string(), value.to_str().ok()?.to_owned()))
+                })
+                .collect(),
+            duration_ms: response.duration.as_millis() as u64,
+            text,
+            body,
+        }
+    }
+
+    /// Load a recording from a file
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        info!("Loading recording `{}`", path.display());
+        let file = File::open(path)
+            .context(format!("Error opening recording `{}`", path.display()))?;
+        serde_json::from_reader(file)
+            .context(format!("Error parsing recording `{}`", path.display()))
+    }
+
+    /// Save this recording to a new file in a directory
+    fn save(&self, dir: &Path) -> anyhow::Result<()> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!(
+            "{}.json",
+            self.recorded_at.format("%Y%m%dT%H%M%S%.3f")
+        ));
+        info!("Saving recording `{}`", path.display());
+        let file = File::create(&path).context(format!(
+            "Error creating recording `{}`",
+            path.display()
+        ))?;
+        serde_json::to_writer_pretty(file, self)
+            .context(format!("Error writing recording `{}`", path.display()))
+    }
+
+    /// Get the response body without shifting timestamps. Useful for tests,
+    /// which need stable times
+    #[cfg(test)]
+    pub fn body(&self) -> &Value {
+        &self.body
+    }
+
+    /// Get the time the response was recorded
+    #[cfg(test)]
+    pub fn recorded_at(&self) -> DateTime<Utc> {
+        self.recorded_at.to_utc()
+    }
+
+    /// Convert to a response, with timestamps shifted to be relative to now
+    fn into_response(mut self) -> HttpResponse {
+        let offset = Utc::now() - self.recorded_at.to_utc();
+        shift_timestamps(&mut self.body, offset);
+        let body = match self.body {
+            Value::String(body) if self.text => body.into_bytes(),
+            body => body.to_string().into_bytes(),
+        };
+        HttpResponse {
+            url: self.url,
+            status: StatusCode::from_u16(self.status).unwrap_or(StatusCode::OK),
+            headers: self
+                .headers
+                .into_iter()
+                .filter_map(|(name, value)| {
+                    Some((
+                        HeaderName::try_from(name).ok()?,
+                        HeaderValue::try_from(value).ok()?,
+                    ))
+                })
+                .collect(),
+            duration: Duration::from_millis(self.duration_ms),
+            body,
+        }
+    }
+}
+
+/// Shift every RFC 3339 timestamp string in a JSON value by an offset.
+/// Timestamps keep their original UTC offset
+fn shift_timestamps(value: &mut Value, offset: TimeDelta) {
+    match value {
+        Value::String(s) => {
+            if let Ok(timestamp) = DateTime::parse_from_rfc3339(s) {
+                *s = (timestamp + offset)
+                    .to_rfc3339_opts(SecondsFormat::AutoSi, false);
+            }
+        }
+        Value::Array(values) => {
+            for value in values {
+                shift_timestamps(value, offset);
+            }
+        }
+        Value::Object(map) => {
+            for value in map.values_mut() {
+                shift_timestamps(value, offset);
+            }
+        }
+        Value::Null | Value::Bool(_) | Value::Number(_) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::{
+        ExternalData,
+        transit::{Alerts, ApiPredictions},
+        weather::Forecast,
+    };
+    use serde_json::json;
+
+    /// Make sure the bundled fixtures match the types they're loaded into
+    #[test]
+    fn test_load_fixtures() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures");
+        let forecast: Forecast = Recording::load(&dir.join("weather.json"))
+            .unwrap()
+            .into_response()
+            .json()
+            .unwrap();
+        assert!(forecast.now().start_time() <= Utc::now());
+        for name in ["transit.json", "schedules.json"] {
+            let _: ApiPredictions = Recording::load(&dir.join(name))
+                .unwrap()
+                .into_response()
+                .json()
+                .unwrap();
+        }
+        let _: <Alerts as ExternalData>::Data =
+            Recording::load(&dir.join("alerts.json"))
+                .unwrap()
+                .into_response()
+                .json()
+                .unwrap();
+    }
+
+    #[test]
+    fn test_recording_round_trip() {
+        let response = HttpResponse {
+            url: "https://example.com/data".into(),
+            status: StatusCode::SERVICE_UNAVAILABLE,
+            headers: [(
+                HeaderName::from_static("x-ratelimit-remaining"),
+                HeaderValue::from_static("7"),
+            )]
+            .into_iter()
+            .collect(),
+            duration: Duration::from_millis(1500),
+            body: b"<html>Down for maintenance</html>".to_vec(),
+        };
+        let recording = Recording::new(&response);
+        assert!(recording.text);
+
+        let replayed = serde_json::from_value::<Recording>(
+            serde_json::to_value(&recording).unwrap(),
+        )
+        .unwrap()
+        .into_response();
+        assert_eq!(replayed.url, response.url);
+        assert_eq!(replayed.status, response.status);
+        assert_eq!(replayed.headers, response.headers);
+        assert_eq!(replayed.duration, response.duration);
+        assert_eq!(replayed.body, response.body);
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let headers: HeaderMap = [
+            (
+                HeaderName::from_static("x-ratelimit-remaining"),
+                HeaderValue::from_static("4"),
+            ),
+            (
+                HeaderName::from_static("x-ratelimit-reset"),
+                HeaderValue::from_static("1749729660"),
+            ),
+        ]
+        .into_iter()
+        .collect();
+        let rate_limit = RateLimit {
+            // 2025-06-12T12:00:00Z
+            reported_at: DateTime::from_timestamp(1749729600, 0).unwrap(),
+            ..RateLimit::from_headers(&headers).unwrap()
+        };
+        assert_eq!(rate_limit.remaining, 4);
+        assert_eq!(rate_limit.reset.to_rfc3339(), "2025-06-12T12:01:00+00:00");
+
+        let now = rate_limit.reported_at;
+        assert_eq!(
+            rate_limit.min_interval(now, 1),
+            Some(Duration::from_secs(15))
+        );
+        // Two services share the quota, so each gets half
+        assert_eq!(
+            rate_limit.min_interval(now, 2),
+            Some(Duration::from_secs(30))
+        );
+        // The quota is exhausted, so wait for the window to end
+        let exhausted = RateLimit {
+            remaining: 0,
+            ..rate_limit
+        };
+        assert_eq!(
+            exhausted.min_interval(now, 1),
+            Some(Duration::from_secs(60))
+        );
+        // The window is over, so the limit no longer applies
+        assert_eq!(
+            rate_limit
+                .min_interval(rate_limit.reset + TimeDelta::seconds(1), 1),
+            None
+        );
+
+        assert_eq!(RateLimit::from_headers(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn test_pollers() {
+        let host = "pollers.example.com";
+        assert_eq!(pollers(host), 1);
+        set_polling(host, "a", true);
+        set_polling(host, "b", true);
+        set_polling(host, "b", true);
+        assert_eq!(pollers(host), 2);
+        set_polling(host, "a", false);
+        assert_eq!(pollers(host), 1);
+        set_polling(host, "b", false);
+        assert_eq!(pollers(host), 1);
+    }
+
+    #[test]
+    fn test_event_stream_parser() {
+        let mut parser = EventStreamParser::default();
+        // Events can be split anywhere across chunks
+        assert_eq!(parser.push(b"event: reset\r\ndata: [1,"), vec![]);
+        assert_eq!(
+            parser.push(b"2]\r\n\r\n: keep-alive\n\nevent: remove\n"),
+            vec![ServerSentEvent {
+                event: "reset".into(),
+                data: "[1,2]".into()
+            }]
+        );
+        assert_eq!(
+            parser.push(b"data: {\"id\": 1}\n\ndata:a\ndata: b\n\n"),
+            vec![
+                ServerSentEvent {
+                    event: "remove".into(),
+                    data: "{\"id\": 1}".into()
+                },
+                ServerSentEvent {
+                    event: "message".into(),
+                    data: "a\nb".into()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_shift_timestamps() {
+        let mut value = json!({
+            "time": "2025-06-12T13:20:00-04:00",
+            "list": ["2025-06-12T17:00:00Z", "not a time", 3],
+            "nested": {"time": "2025-06-12T13:25:30.5-04:00"},
+        });
+        shift_timestamps(&mut value, TimeDelta::minutes(90));
+        assert_eq!(
+            value,
+            json!({
+                "time": "2025-06-12T14:50:00-04:00",
+                "list": ["2025-06-12T18:30:00+00:00", "not a time", 3],
+                "nested": {"time": "2025-06-12T14:55:30.500-04:00"},
+            })
+        );
+    }
+}
@@ -0,0 +1,34 @@
+//! Helpers shared by the services' tests
+
+use crate::{config::Config, services::http::Recording};
+use serde::de::DeserializeOwned;
+use serde_json::{Value, json};
+use std::path::{Path, PathBuf};
+
+/// Get the path of a file in `fixtures/`
+pub fn fixture_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("fixtures")
+        .join(name)
+}
+
+/// Load the response body of a recorded fixture, e.g. `weather.json`
+pub fn load_fixture<T: DeserializeOwned>(name: &str) -> T {
+    let recording = Recording::load(&fixture_path(name)).unwrap();
+    serde_json::from_value(recording.body().clone()).unwrap()
+}
+
+/// Build a config with only the required fields, plus the given fields on
+/// top. This isn't validated, so the weather location can be left out. Data
+/// is cached in the real cache directory unless `cache_dir` is given
+pub fn config(fields: Value) -> Config {
+    let mut config = json!({
+        "window_size": [720, 720],
+        "transit_lines": [],
+    });
+    config
+        .as_object_mut()
+        .unwrap()
+        .extend(fields.as_object().unwrap().clone());
+    serde_json::from_value(config).unwrap()
+}
@@ -1,149 +1,656 @@
+mod alerts;
+mod gtfs_realtime;
+mod gtfs_static;
+mod schedule;
+mod stream;
+
+pub use alerts::{Alert, Alerts};
+pub use gtfs_static::GtfsStatic;
+pub use schedule::Schedules;
+pub use stream::StreamEvent;
+
 use crate::{
     Message,
     config::Config,
-    services::{CLIENT, ExternalData, FetchedData},
+    services::{
+        CLIENT, DataStore, ExternalData, FetchError, FetchResult, FetchedData,
+        deserialize_id,
+        http::{Backend, fetch, fetch_json},
+        save_cache,
+        transit::{
+            gtfs_realtime::GtfsRealtimeData, gtfs_static::GtfsStaticData,
+        },
+    },
 };
 use anyhow::Context;
-use chrono::{DateTime, Utc};
-use indexmap::IndexMap;
+use chrono::{DateTime, TimeDelta, Utc};
+use iced::Subscription;
 use itertools::Itertools;
-use log::{error, info};
-use serde::Deserialize;
-use std::{fmt::Display, time::Duration};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    fmt::Display,
+    iter,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// Host of the MBTA v3 API
+const API_HOST: &str = "api-v3.mbta.com";
+/// Environment variable for the API key. Takes precedence over the config
+const API_KEY_VARIABLE: &str = "MBTA_API_KEY";
+/// Counter to give each [Transit] instance a unique stream subscription
+static STREAM_GENERATION: AtomicU64 = AtomicU64::new(0);
 
 #[derive(Debug)]
 pub struct Transit {
+    provider: TransitProvider,
     url: String,
+    /// Optional MBTA API key, to raise the rate limit
+    api_key: Option<String>,
     lines: Vec<TransitLine>,
+    /// URL to stream prediction updates from. `None` if streaming is disabled
+    stream_url: Option<String>,
+    /// Is the stream connected? If so, polling is paused
+    is_streaming: bool,
+    /// Unique to this instance, so a rebuilt instance gets a new stream and
+    /// its own reset, rather than updates to the old instance's data
+    stream_generation: u64,
+    /// Show countdowns to the second, instead of whole minutes
+    countdown_seconds: bool,
     /// Prediction data loaded from the API
-    data: Option<FetchedData<ApiPredictions>>,
+    store: DataStore<TransitData>,
 }
 
 impl Transit {
-    /// Max number of pending departures to show for a stop
-    const MAX_PREDICTIONS: usize = 3;
+    /// Prediction attributes that we use. Everything else is excluded from
+    /// responses to keep them small
+    const PREDICTION_FIELDS: &[&str] = &[
+        "arrival_time",
+        "departure_time",
+        "direction_id",
+        "schedule_relationship",
+        "status",
+    ];
+    /// Trip attributes that we use
+    const TRIP_FIELDS: &[&str] = &["headsign"];
+    /// Vehicle attributes that we use
+    const VEHICLE_FIELDS: &[&str] = &["current_status", "occupancy_status"];
 
     pub fn new(config: &Config) -> Self {
-        let all_stops = config
-            .transit_lines
-            .iter()
-            .flat_map(|line| &line.stops)
-            .map(|stop| stop.id);
-        let url = format!(
-            "https://api-v3.mbta.com/predictions?filter[stop]={}",
-            all_stops.format(",")
-        );
+        let (url, stream_url) = match &config.transit_provider {
+            TransitProvider::Mbta => Self::mbta_urls(config),
+            TransitProvider::GtfsRealtime {
+                trip_updates_url, ..
+            } => (trip_updates_url.clone(), None),
+        };
         Self {
+            provider: config.transit_provider.clone(),
             url,
+            stream_url,
+            is_streaming: false,
+            stream_generation: STREAM_GENERATION
+                .fetch_add(1, Ordering::Relaxed),
+            countdown_seconds: config.transit_countdown_seconds,
+            api_key: match config.transit_provider {
+                TransitProvider::Mbta => api_key(config),
+                TransitProvider::GtfsRealtime { .. } => None,
+            },
             lines: config.transit_lines.clone(),
-            data: None,
+            store: DataStore::new(
+                config.refresh.schedule::<Self>(),
+                config.cache_path::<Self>(),
+            ),
+        }
+    }
+
+    /// Get the URLs to fetch and stream MBTA predictions from. The stream URL
+    /// is `None` if streaming is disabled
+    fn mbta_urls(config: &Config) -> (String, Option<String>) {
+        let mut params =
+            filter_params(&config.transit_lines, &["stop", "trip", "vehicle"]);
+        params.extend([
+            ("fields[prediction]", Self::PREDICTION_FIELDS.join(",")),
+            ("fields[trip]", Self::TRIP_FIELDS.join(",")),
+            ("fields[vehicle]", Self::VEHICLE_FIELDS.join(",")),
+        ]);
+        // Streams can't be recorded or mocked, so only stream live data.
+        // Streamed updates are sent in any order, so they're sorted locally
+        let stream_url = (config.transit_streaming
+            && matches!(Backend::get(), Backend::Live))
+        .then(|| api_url("predictions", &params));
+        params.push(("sort", "departure_time".to_owned()));
+        (api_url("predictions", &params), stream_url)
+    }
+
+    /// Stream prediction updates for as long as this instance is in use. The
+    /// subscription is restarted when a config reload rebuilds the instance
+    pub fn subscription(&self) -> Subscription<Message> {
+        let Some(url) = &self.stream_url else {
+            return Subscription::none();
+        };
+        Subscription::run_with_id(
+            self.stream_generation,
+            stream::connect(url.clone(), self.api_key.clone()),
+        )
+    }
+
+    /// Apply an update from the prediction stream. If the stream dropped,
+    /// fall back to polling until it reconnects
+    pub fn handle_stream(&mut self, result: Result<StreamEvent, FetchError>) {
+        match result {
+            Ok(StreamEvent::Reset(predictions)) => {
+                self.is_streaming = true;
+                let data = FetchedData::new(TransitData::Mbta(predictions));
+                save_cache(self, &data);
+                // A poll may still be in flight. Its result will be dropped,
+                // but it still counts against the fetch limit until then
+                let in_flight = self.store.in_flight;
+                self.store_result(Ok(data));
+                self.store.in_flight = in_flight;
+            }
+            // Updates that arrive after a disconnect are from an old
+            // connection, and will be superseded by the next reset
+            Ok(event) if self.is_streaming => {
+                self.store.update(|data| {
+                    if let TransitData::Mbta(predictions) = data {
+                        predictions.apply(event);
+                    }
+                });
+            }
+            Ok(_) => {}
+            Err(error) => {
+                warn!("Polling transit until the stream reconnects: {error}");
+                self.is_streaming = false;
+            }
         }
     }
 
-    /// Get predictions for all stops on all lines
-    pub fn predictions(&self) -> Predictions {
-        // Group predictions as (line, stops)
-        let mut grouped: IndexMap<String, Vec<StopPrediction>> = self
+    /// Get predictions for all stops on all lines. Scheduled departures fill
+    /// in for trips that don't have a prediction. The static GTFS feed is only
+    /// used by the GTFS-Realtime provider
+    pub fn predictions(
+        &self,
+        schedules: &Schedules,
+        gtfs_static: &GtfsStatic,
+    ) -> Predictions {
+        self.predictions_at(
+            schedules.data().map(|data| &data.data),
+            gtfs_static.data().map(|data| &data.data),
+            Utc::now(),
+        )
+    }
+
+    /// Get predictions for all stops on all lines, with countdowns relative to
+    /// the given time
+    fn predictions_at(
+        &self,
+        schedules: Option<&ApiPredictions>,
+        gtfs_static: Option<&GtfsStaticData>,
+        now: DateTime<Utc>,
+    ) -> Predictions {
+        // We want to show empty data if we don't have an API response yet
+        let mut lines: Vec<LinePrediction> = self
             .lines
             .iter()
-            .map(|line| {
-                (
-                    line.name.clone(),
-                    line.stops
-                        .iter()
-                        .map(|stop| StopPrediction {
-                            id: stop.id,
-                            name: stop.name.clone(),
-                            predictions: CountdownList::default(),
-                        })
-                        .collect(),
-                )
+            .map(|line| LinePrediction {
+                name: line.name.clone(),
+                stops: line
+                    .stops
+                    .iter()
+                    .map(|stop| StopPrediction {
+                        name: stop.name.clone(),
+                        predictions: CountdownList::default(),
+                    })
+                    .collect(),
             })
             .collect();
 
+        // Trips with a prediction at a stop, including skipped stops. The
+        // schedule for these is outdated
+        let mut predicted_trips = HashSet::new();
+        // Scheduled times for each trip at each stop. Cancelled and skipped
+        // predictions don't have times, so we show the scheduled time instead
+        let schedules = schedules
+            .map(ApiPredictions::departures)
+            .unwrap_or_default();
+        let scheduled_times: HashMap<_, _> = schedules
+            .iter()
+            .filter_map(|schedule| Some((schedule.trip_stop()?, schedule)))
+            .collect();
+
         // Pull data from the most recent response
-        if let Some(data) = &self.data {
-            for prediction in &data.data.data {
-                // Departure time will be empty if the stop is being skipped
-                let Some(departure_time) = prediction.attributes.departure_time
-                else {
-                    continue;
-                };
-                let route_id = &prediction.relationships.route.data.id;
-                let Some(stops) = grouped.get_mut(route_id) else {
-                    error!("Unknown route {route_id}");
-                    continue;
-                };
-                let stop_id = &prediction.relationships.stop.data.id;
-
-                let Some(stop_prediction) = stops
-                    .iter_mut()
-                    .find(|stop| &stop.id.to_string() == stop_id)
-                else {
-                    error!("Unknown stop {stop_id} for route {route_id}");
-                    continue;
-                };
-                stop_prediction.predictions.push(departure_time);
+        let departures = match self.data().map(|data| &data.data) {
+            Some(TransitData::Mbta(predictions)) => predictions.departures(),
+            Some(TransitData::GtfsRealtime(feed)) => {
+                feed.departures(&self.lines, gtfs_static)
+            }
+            None => Vec::new(),
+        };
+        for departure in &departures {
+            predicted_trips.extend(departure.trip_stop());
+            let Some((stop, output)) = self.find_stop(&mut lines, departure)
+            else {
+                continue;
+            };
+            let time = departure.time(stop.countdown_to).or_else(|| {
+                let schedule = scheduled_times.get(&departure.trip_stop()?)?;
+                schedule.time(stop.countdown_to)
+            });
+            // Without a time, there's nothing to show unless the trip isn't
+            // stopping here or there's a status to show instead
+            if time.is_none()
+                && !departure.is_removed()
+                && departure.status.is_none()
+            {
+                continue;
+            }
+            output.predictions.push(
+                Countdown::predicted(departure, time, now)
+                    .for_stop(stop, self.countdown_seconds),
+            );
+        }
+
+        for schedule in &schedules {
+            if schedule
+                .trip_stop()
+                .is_some_and(|trip_stop| predicted_trips.contains(&trip_stop))
+            {
+                continue;
+            }
+            let Some((stop, output)) = self.find_stop(&mut lines, schedule)
+            else {
+                continue;
+            };
+            // Schedules for the whole day are fetched, so skip past departures
+            let Some(time) =
+                schedule.time(stop.countdown_to).filter(|&time| time >= now)
+            else {
+                continue;
+            };
+            output.predictions.push(
+                Countdown::scheduled(time, now)
+                    .for_stop(stop, self.countdown_seconds),
+            );
+        }
+
+        // Countdowns are collected for every departure so that the soonest
+        // ones are kept, regardless of the order they came in
+        for (line, line_prediction) in self.lines.iter().zip(&mut lines) {
+            for (stop, stop_prediction) in
+                line.stops.iter().zip(&mut line_prediction.stops)
+            {
+                stop_prediction.predictions.truncate(stop.max_predictions);
             }
         }
 
-        // We want to show empty data if we don't have an API response yet
-        let lines = grouped
-            .into_iter()
-            .map(|(name, stops)| LinePrediction { name, stops })
-            .collect();
         Predictions { lines }
     }
+
+    /// Find the configured stop that a prediction or schedule belongs to,
+    /// and its output. `None` if no configured stop wants it
+    fn find_stop<'a>(
+        &'a self,
+        lines: &'a mut [LinePrediction],
+        departure: &Departure,
+    ) -> Option<(&'a TransitStop, &'a mut StopPrediction)> {
+        let route_id = departure.route_id;
+        let stop_id = departure.stop_id;
+        // Predictions are always for a child stop (e.g. a platform). If the
+        // parent station is configured, accept any of its children
+        let parent_id = departure.parent_station_id;
+
+        // A route can be shown on multiple lines, e.g. with different stops on
+        // each. Find the first stop that wants this prediction, pairing each
+        // stop's config with its output
+        let mut candidates = self
+            .lines
+            .iter()
+            .zip(lines)
+            .filter(|(line, _)| line.has_route(route_id))
+            .flat_map(|(line, line_prediction)| {
+                line.stops.iter().zip(&mut line_prediction.stops)
+            })
+            .peekable();
+        // This runs on every render, so these are too noisy for anything
+        // above debug
+        if candidates.peek().is_none() {
+            debug!("Unknown route {route_id}");
+            return None;
+        }
+        let mut candidates = candidates
+            .filter(|(stop, _)| {
+                stop.id == stop_id || Some(stop.id.as_str()) == parent_id
+            })
+            .peekable();
+        if candidates.peek().is_none() {
+            debug!("Unknown stop {stop_id} for route {route_id}");
+            return None;
+        }
+        // The stop may be configured to exclude this route or direction.
+        // That's expected, since we can't always filter those out in the
+        // request
+        let direction_id = departure.direction_id;
+        candidates.find(|(stop, _)| stop.accepts(route_id, direction_id))
+    }
 }
 
 impl ExternalData for Transit {
-    const TTL: Duration = Duration::from_secs(30);
-    type Data = ApiPredictions;
+    const NAME: &'static str = "transit";
+    const DEFAULT_TTL: Duration = Duration::from_secs(30);
+    const RATE_LIMITED_HOST: Option<&'static str> = Some(API_HOST);
+    type Data = TransitData;
 
-    fn data(&self) -> Option<&FetchedData<Self::Data>> {
-        self.data.as_ref()
+    fn same_settings(&self, other: &Self) -> bool {
+        self.provider == other.provider
+            && self.url == other.url
+            && self.stream_url == other.stream_url
+            && self.api_key == other.api_key
+            && self.lines == other.lines
+            && self.countdown_seconds == other.countdown_seconds
+            && self.store.refresh == other.store.refresh
     }
 
-    fn set_data(&mut self, data: FetchedData<Self::Data>) {
-        self.data = Some(data);
+    fn cache_key(&self) -> String {
+        match self.provider {
+            // The URL includes all the filters
+            TransitProvider::Mbta => self.url.clone(),
+            // Trip updates are filtered by route after they're fetched
+            TransitProvider::GtfsRealtime { .. } => {
+                format!("{}#{}", self.url, lines_key(&self.lines))
+            }
+        }
     }
 
-    fn data_to_message(data: FetchedData<Self::Data>) -> Message {
-        Message::TransitFetched(data)
+    fn store(&self) -> &DataStore<Self::Data> {
+        &self.store
+    }
+
+    fn store_mut(&mut self) -> &mut DataStore<Self::Data> {
+        &mut self.store
+    }
+
+    fn result_to_message(result: FetchResult<Self::Data>) -> Message {
+        Message::TransitFetched(result)
+    }
+
+    fn is_streaming(&self) -> bool {
+        self.is_streaming
     }
 
     fn fetch(
         &self,
     ) -> impl 'static + Future<Output = anyhow::Result<Self::Data>> + Send {
         info!("Fetching transit data from {}", self.url);
-        let request = CLIENT.get(&self.url);
+        let request = request(&self.url, self.api_key.as_deref());
+        let provider = self.provider.clone();
+        let lines = self.lines.clone();
         async move {
-            let response =
-                request.send().await.context("Error fetching transit")?;
-            response
-                .error_for_status()?
-                .json()
-                .await
-                .context("Error parsing transit")
+            match provider {
+                TransitProvider::Mbta => {
+                    fetch_json(Self::NAME, request).await.map(TransitData::Mbta)
+                }
+                TransitProvider::GtfsRealtime { .. } => {
+                    let response = fetch(Self::NAME, request).await?;
+                    GtfsRealtimeData::decode(&response.body, &lines)
+                        .map(TransitData::GtfsRealtime)
+                }
+            }
+            .context("Error fetching transit")
         }
     }
 }
 
+/// Get query params to filter a request to the stops, routes, and directions
+/// of the configured lines, and to include related resources. Stops should
+/// always be included so we can map child stops back to parent stations
+fn filter_params(
+    lines: &[TransitLine],
+    include: &[&str],
+) -> Vec<(&'static str, String)> {
+    let mut params = stop_route_params(lines);
+    params.push(("include", include.join(",")));
+    // The direction filter applies to the whole request, so we can only use
+    // it if every stop wants the same direction. Otherwise, we filter by
+    // direction after the fact
+    if let Ok(Some(direction_id)) = lines
+        .iter()
+        .flat_map(|line| &line.stops)
+        .map(|stop| stop.direction_id)
+        .all_equal_value()
+    {
+        params.push(("filter[direction_id]", direction_id.to_string()));
+    }
+    params
+}
+
+/// Get query params to filter a request to the stops and routes of the
+/// configured lines
+fn stop_route_params(lines: &[TransitLine]) -> Vec<(&'static str, String)> {
+    let mut all_stops = lines
+        .iter()
+        .flat_map(|line| &line.stops)
+        .map(|stop| &stop.id)
+        .unique();
+    let mut all_routes = lines.iter().flat_map(TransitLine::route_ids).unique();
+    vec![
+        ("filter[stop]", all_stops.join(",")),
+        ("filter[route]", all_routes.join(",")),
+    ]
+}
+
+/// Build the URL for an API endpoint, e.g. `predictions`
+fn api_url(endpoint: &str, params: &[(&str, String)]) -> String {
+    format!(
+        "https://{API_HOST}/{endpoint}?{}",
+        params
+            .iter()
+            .format_with("&", |(param, value), f| f(&format_args!(
+                "{param}={value}"
+            )))
+    )
+}
+
+/// Identify the routes, stops, and directions of the configured lines, for
+/// cache keys of data that's filtered by them
+fn lines_key(lines: &[TransitLine]) -> String {
+    lines
+        .iter()
+        .map(|line| {
+            let stops = line.stops.iter().map(|stop| match stop.direction_id {
+                Some(direction_id) => format!("{}/{direction_id}", stop.id),
+                None => stop.id.clone(),
+            });
+            format!("{}@{}", line.route_ids().format("+"), stops.format("+"))
+        })
+        .join(",")
+}
+
+/// Get the API key from the environment or config, if there is one
+fn api_key(config: &Config) -> Option<String> {
+    env::var(API_KEY_VARIABLE)
+        .ok()
+        .or_else(|| config.mbta_api_key.clone())
+}
+
+/// Build a request to the API, with the API key if there is one
+fn request(url: &str, api_key: Option<&str>) -> reqwest::RequestBuilder {
+    authorize(CLIENT.get(url), api_key)
+}
+
+/// Add the API key to a request, if there is one
+fn authorize(
+    request: reqwest::RequestBuilder,
+    api_key: Option<&str>,
+) -> reqwest::RequestBuilder {
+    match api_key {
+        Some(api_key) => request.header("x-api-key", api_key),
+        None => request,
+    }
+}
+
+/// Where to get transit predictions from
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TransitProvider {
+    /// The MBTA v3 API, which also provides schedules, alerts, and streaming
+    #[default]
+    Mbta,
+    /// Any agency that publishes a GTFS-Realtime feed. Stop and route IDs in
+    /// the config are the agency's GTFS IDs
+    GtfsRealtime {
+        /// URL of the TripUpdates feed (protobuf)
+        trip_updates_url: String,
+        /// URL of the static GTFS feed (zip), for parent stations and trip
+        /// details
+        static_url: String,
+    },
+}
+
+impl TransitProvider {
+    /// Is this the MBTA? Schedules, alerts, and streaming are only available
+    /// from the MBTA API
+    pub fn is_mbta(&self) -> bool {
+        matches!(self, Self::Mbta)
+    }
+}
+
 /// Configuration for a transit line to show predictions for
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize)]
 pub struct TransitLine {
+    /// Display name for the line
     pub name: String,
+    /// API IDs of the routes to show predictions for, e.g. `["47", "CT2"]`.
+    /// If omitted, the name is used as the route ID
+    #[serde(default)]
+    pub routes: Vec<String>,
     /// Stops on the line to monitor
     pub stops: Vec<TransitStop>,
 }
 
+impl TransitLine {
+    /// Get the IDs of all routes shown on this line
+    fn route_ids(&self) -> Box<dyn '_ + Iterator<Item = &str>> {
+        if self.routes.is_empty() {
+            Box::new(iter::once(self.name.as_str()))
+        } else {
+            Box::new(self.routes.iter().map(String::as_str))
+        }
+    }
+
+    /// Does this line show predictions for the given route?
+    pub fn has_route(&self, route_id: &str) -> bool {
+        self.route_ids().any(|route| route == route_id)
+    }
+}
+
 /// Configuration for a single stop on a transit line
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize)]
 pub struct TransitStop {
     /// Display name for the stop/direction
     pub name: String,
-    /// API ID of the stop
-    pub id: u32,
+    /// API ID of the stop, e.g. `2545` or `70061`. This can also be a parent
+    /// station, e.g. `place-sstat`, to include all of its platforms
+    #[serde(deserialize_with = "deserialize_id")]
+    pub id: String,
+    /// Only show predictions going in this direction (`0` or `1`). The
+    /// meaning of each direction varies by route
+    pub direction_id: Option<u8>,
+    /// Only show predictions for these routes. If omitted, all of the line's
+    /// routes are shown
+    #[serde(default)]
+    pub routes: Vec<String>,
+    /// Count down to when vehicles arrive at or depart from the stop. If the
+    /// chosen time isn't available (e.g. departures at the end of the line),
+    /// the other is used
+    #[serde(default)]
+    pub countdown_to: CountdownTo,
+    /// Minutes it takes to walk to the stop. Departures sooner than this
+    /// can't be caught, so they're greyed out
+    #[serde(default)]
+    pub walk_minutes: u32,
+    /// Max number of upcoming departures to show
+    #[serde(default = "TransitStop::default_max_predictions")]
+    pub max_predictions: usize,
+}
+
+impl TransitStop {
+    fn default_max_predictions() -> usize {
+        3
+    }
+
+    /// Should a prediction for the given route and direction be shown for
+    /// this stop? This assumes the stop ID has already been matched
+    fn accepts(&self, route_id: &str, direction_id: Option<u8>) -> bool {
+        (self.routes.is_empty() || self.routes.iter().any(|r| r == route_id))
+            && (self.direction_id.is_none()
+                || self.direction_id == direction_id)
+    }
+}
+
+/// Which time to count down to for a stop
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CountdownTo {
+    #[default]
+    Departure,
+    Arrival,
+}
+
+/// A trip's predicted or scheduled stop, independent of where the data came
+/// from. Each provider converts its data into these, so countdowns can be
+/// built the same way for all of them
+#[derive(Debug)]
+struct Departure<'a> {
+    trip_id: Option<&'a str>,
+    route_id: &'a str,
+    stop_id: &'a str,
+    /// Parent station of the stop, if it has one
+    parent_station_id: Option<&'a str>,
+    direction_id: Option<u8>,
+    /// Empty at the start of the line
+    arrival_time: Option<DateTime<Utc>>,
+    /// Empty at the end of the line
+    departure_time: Option<DateTime<Utc>>,
+    /// `None` for a regularly scheduled trip
+    schedule_relationship: Option<ScheduleRelationship>,
+    /// Free-text status, e.g. `Stopped 3 stops away`
+    status: Option<&'a str>,
+    /// Destination of the trip, e.g. `Forest Hills`
+    headsign: Option<&'a str>,
+    /// Status of the vehicle, if it's at or approaching this stop
+    vehicle_status: Option<VehicleStatus>,
+    occupancy: Option<Occupancy>,
+}
+
+impl Departure<'_> {
+    /// Get the IDs of the trip and stop, which together identify a single
+    /// departure
+    fn trip_stop(&self) -> Option<(&str, &str)> {
+        Some((self.trip_id?, self.stop_id))
+    }
+
+    /// Get the time to count down to, falling back to the other time if the
+    /// preferred one isn't available. Both are empty if the trip is
+    /// cancelled or skipping the stop
+    fn time(&self, countdown_to: CountdownTo) -> Option<DateTime<Utc>> {
+        match countdown_to {
+            CountdownTo::Departure => self.departure_time.or(self.arrival_time),
+            CountdownTo::Arrival => self.arrival_time.or(self.departure_time),
+        }
+    }
+
+    /// Is the trip cancelled, or skipping this stop?
+    fn is_removed(&self) -> bool {
+        matches!(
+            self.schedule_relationship,
+            Some(
+                ScheduleRelationship::Cancelled | ScheduleRelationship::Skipped
+            )
+        )
+    }
 }
 
 #[derive(Debug)]
@@ -159,7 +666,6 @@ pub struct LinePrediction {
 
 #[derive(Debug)]
 pub struct StopPrediction {
-    pub id: u32,
     pub name: String,
     pub predictions: CountdownList,
 }
@@ -168,72 +674,852 @@ pub struct StopPrediction {
 pub struct CountdownList(Vec<Countdown>);
 
 impl CountdownList {
-    /// Add a timestamp to the list of countdowns. If already at max size, throw
-    /// it away
-    fn push(&mut self, departure_time: DateTime<Utc>) {
-        if self.0.len() < Transit::MAX_PREDICTIONS {
-            let now = Utc::now();
-            let countdown = Countdown((departure_time - now).num_minutes());
-            self.0.push(countdown);
+    /// Add a countdown to the list in order of time. Departures that have
+    /// already left are dropped
+    fn push(&mut self, countdown: Countdown) {
+        if countdown.has_departed() {
+            return;
         }
+        let index = self
+            .0
+            .partition_point(|other| other.sort_key() <= countdown.sort_key());
+        self.0.insert(index, countdown);
+    }
+
+    /// Keep only the soonest departures
+    fn truncate(&mut self, len: usize) {
+        self.0.truncate(len);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Countdown> {
+        self.0.iter()
     }
 }
 
 impl Display for CountdownList {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         // Format countdowns as "1m, 5m, 10m"
-        write!(
-            f,
-            "{}",
-            self.0
-                .iter()
-                .format_with(", ", |countdown, f| f(&format_args!(
-                    "{}m",
-                    countdown.0
-                )))
-        )
+        write!(f, "{}", self.0.iter().format(", "))
     }
 }
 
-/// Number of minutes until an event
+/// Time until a departure, with details on the trip and vehicle if available
 #[derive(Debug)]
-pub struct Countdown(i64);
+pub struct Countdown {
+    /// Time until the departure. Negative if it's overdue. `None` if the time
+    /// is unknown, e.g. for a cancelled trip that isn't in the schedule
+    remaining: Option<TimeDelta>,
+    /// Departs before we could walk to the stop
+    missed: bool,
+    /// Show the time remaining to the second, instead of whole minutes
+    show_seconds: bool,
+    /// Is this from the schedule, rather than a realtime prediction?
+    scheduled: bool,
+    /// Is the trip added, cancelled, or skipping this stop?
+    schedule_relationship: Option<ScheduleRelationship>,
+    /// Text to show in place of a time, e.g. `Stopped 3 stops away`
+    status: Option<String>,
+    /// Status of the vehicle, if it's at or approaching this stop
+    vehicle_status: Option<VehicleStatus>,
+    /// Destination of the trip, e.g. `Forest Hills`
+    headsign: Option<String>,
+    occupancy: Option<Occupancy>,
+}
+
+impl Countdown {
+    /// Departures sooner than this (or overdue) are shown as `Now`
+    const NOW: TimeDelta = TimeDelta::seconds(30);
+    /// Departures sooner than this are shown as `Due`
+    const DUE: TimeDelta = TimeDelta::minutes(1);
+
+    /// Build a countdown from a realtime prediction
+    fn predicted(
+        departure: &Departure,
+        time: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            remaining: time.map(|time| time - now),
+            missed: false,
+            show_seconds: false,
+            scheduled: false,
+            schedule_relationship: departure.schedule_relationship,
+            status: departure.status.map(str::to_owned),
+            // If the trip isn't stopping here, the vehicle doesn't matter
+            vehicle_status: departure
+                .vehicle_status
+                .filter(|_| !departure.is_removed()),
+            headsign: departure.headsign.map(str::to_owned),
+            occupancy: departure.occupancy,
+        }
+    }
+
+    /// Build a countdown from a scheduled departure
+    fn scheduled(time: DateTime<Utc>, now: DateTime<Utc>) -> Self {
+        Self {
+            remaining: Some(time - now),
+            missed: false,
+            show_seconds: false,
+            scheduled: true,
+            schedule_relationship: None,
+            status: None,
+            vehicle_status: None,
+            headsign: None,
+            occupancy: None,
+        }
+    }
+
+    /// Apply display settings for the stop that the departure is from
+    fn for_stop(mut self, stop: &TransitStop, show_seconds: bool) -> Self {
+        let walk_time = TimeDelta::minutes(stop.walk_minutes.into());
+        self.missed = self
+            .remaining
+            .is_some_and(|remaining| remaining < walk_time);
+        self.show_seconds = show_seconds;
+        self
+    }
+
+    /// Sort by time, with unknown times last
+    fn sort_key(&self) -> (bool, TimeDelta) {
+        (self.remaining.is_none(), self.remaining.unwrap_or_default())
+    }
+
+    /// Has the departure time passed? Vehicles that are still at the stop are
+    /// running late, so they haven't departed
+    fn has_departed(&self) -> bool {
+        self.remaining
+            .is_some_and(|remaining| remaining < TimeDelta::zero())
+            && self.vehicle_status != Some(VehicleStatus::StoppedAt)
+    }
+
+    /// Does this depart before we could walk to the stop?
+    pub fn is_missed(&self) -> bool {
+        self.missed
+    }
+
+    /// Is the trip cancelled, or skipping this stop?
+    pub fn is_removed(&self) -> bool {
+        self.removal().is_some()
+    }
+
+    /// Describe why the trip isn't stopping here, if it isn't
+    fn removal(&self) -> Option<&'static str> {
+        match self.schedule_relationship? {
+            ScheduleRelationship::Cancelled => Some("cancelled"),
+            ScheduleRelationship::Skipped => Some("skipped"),
+            ScheduleRelationship::Added
+            | ScheduleRelationship::NoData
+            | ScheduleRelationship::Unscheduled
+            | ScheduleRelationship::Unknown => None,
+        }
+    }
+
+    pub fn headsign(&self) -> Option<&str> {
+        self.headsign.as_deref()
+    }
+
+    pub fn occupancy(&self) -> Option<Occupancy> {
+        self.occupancy
+    }
+}
 
 impl Display for Countdown {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0)
+        let Some(remaining) = self.remaining else {
+            // Show whatever we know in place of the time, e.g. `Cancelled`
+            return match (self.schedule_relationship, &self.status) {
+                (Some(ScheduleRelationship::Cancelled), _) => {
+                    write!(f, "Cancelled")
+                }
+                (Some(ScheduleRelationship::Skipped), _) => {
+                    write!(f, "Skipped")
+                }
+                (_, Some(status)) => write!(f, "{status}"),
+                _ => Ok(()),
+            };
+        };
+        match self.vehicle_status {
+            Some(VehicleStatus::StoppedAt) => write!(f, "Boarding"),
+            Some(VehicleStatus::IncomingAt) => write!(f, "Arriving"),
+            Some(VehicleStatus::InTransitTo | VehicleStatus::Unknown)
+            | None => {
+                if remaining < Self::NOW {
+                    write!(f, "Now")?;
+                } else if remaining < Self::DUE {
+                    write!(f, "Due")?;
+                } else {
+                    // Scheduled times are approximate, so mark them with a ~
+                    if self.scheduled {
+                        write!(f, "~")?;
+                    }
+                    let minutes = remaining.num_minutes();
+                    if self.show_seconds {
+                        let seconds = remaining.num_seconds() % 60;
+                        write!(f, "{minutes}:{seconds:02}")?;
+                    } else {
+                        write!(f, "{minutes}m")?;
+                    }
+                }
+                if let Some(removal) = self.removal() {
+                    write!(f, " {removal}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// How a predicted trip relates to the schedule
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+enum ScheduleRelationship {
+    /// An extra trip that isn't in the schedule
+    Added,
+    /// The whole trip is cancelled
+    Cancelled,
+    NoData,
+    /// The trip won't stop at this stop
+    Skipped,
+    /// A trip that runs on a frequency rather than a fixed schedule
+    Unscheduled,
+    #[serde(other)]
+    Unknown,
+}
+
+/// Where a vehicle is relative to its current stop
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+enum VehicleStatus {
+    /// About to arrive at the stop
+    IncomingAt,
+    /// Stopped at the stop
+    StoppedAt,
+    /// Departed the previous stop, in transit to this one
+    InTransitTo,
+    #[serde(other)]
+    Unknown,
+}
+
+/// How crowded a vehicle is
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Occupancy {
+    ManySeatsAvailable,
+    FewSeatsAvailable,
+    StandingRoomOnly,
+    CrushedStandingRoomOnly,
+    Full,
+    NotAcceptingPassengers,
+    #[serde(other)]
+    NoDataAvailable,
+}
+
+impl Occupancy {
+    /// Get a crowding indicator, from one to three filled dots. `None` if
+    /// there's no data
+    pub fn icon(self) -> Option<&'static str> {
+        match self {
+            Self::ManySeatsAvailable => Some("●○○"),
+            Self::FewSeatsAvailable => Some("●●○"),
+            Self::StandingRoomOnly
+            | Self::CrushedStandingRoomOnly
+            | Self::Full
+            | Self::NotAcceptingPassengers => Some("●●●"),
+            Self::NoDataAvailable => None,
+        }
     }
 }
 
+/// Predictions from any provider
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum TransitData {
+    Mbta(ApiPredictions),
+    GtfsRealtime(GtfsRealtimeData),
+}
+
 /// <https://api-v3.mbta.com/docs/swagger/index.html#/Prediction/ApiWeb_PredictionController_index>
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ApiPredictions {
     data: Vec<Prediction>,
+    /// Related resources requested via the `include` param
+    #[serde(default)]
+    included: Vec<Included>,
 }
 
-#[derive(Clone, Debug, Deserialize)]
-struct Prediction {
+impl ApiPredictions {
+    /// Get a departure for each prediction or schedule, with details from
+    /// the included resources
+    fn departures(&self) -> Vec<Departure<'_>> {
+        let parent_stations = self.parent_stations();
+        self.data
+            .iter()
+            .map(|prediction| {
+                let attributes = &prediction.attributes;
+                let relationships = &prediction.relationships;
+                let stop_id = relationships.stop.data.id.as_str();
+                let trip_id = relationships
+                    .trip
+                    .as_ref()
+                    .map(|trip| trip.data.id.as_str());
+                let vehicle = relationships
+                    .vehicle
+                    .as_ref()
+                    .and_then(|vehicle| vehicle.data.as_ref())
+                    .and_then(|vehicle| self.vehicle(&vehicle.id));
+                Departure {
+                    trip_id,
+                    route_id: &relationships.route.data.id,
+                    stop_id,
+                    parent_station_id: parent_stations.get(stop_id).copied(),
+                    direction_id: attributes.direction_id,
+                    arrival_time: attributes.arrival_time,
+                    departure_time: attributes.departure_time,
+                    schedule_relationship: attributes.schedule_relationship,
+                    status: attributes.status.as_deref(),
+                    headsign: trip_id
+                        .and_then(|trip_id| self.trip(trip_id))
+                        .map(|trip| trip.attributes.headsign.as_str()),
+                    // The vehicle's status is relative to its current stop,
+                    // which may not be this one
+                    vehicle_status: vehicle
+                        .filter(|vehicle| {
+                            vehicle.stop_id().is_some_and(|id| id == stop_id)
+                        })
+                        .and_then(|vehicle| vehicle.attributes.current_status),
+                    occupancy: vehicle.and_then(|vehicle| {
+                        vehicle.attributes.occupancy_status
+                    }),
+                }
+            })
+            .collect()
+    }
+
+    /// Get a map of stop ID to parent station ID, for all included stops that
+    /// have a parent
+    fn parent_stations(&self) -> HashMap<&str, &str> {
+        self.included
+            .iter()
+            .filter_map(|included| match included {
+                Included::Stop(stop) => {
+                    let parent =
+                        stop.relationships.parent_station.data.as_ref()?;
+                    Some((stop.id.as_str(), parent.id.as_str()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Get an included trip by ID
+    fn trip(&self, id: &str) -> Option<&ApiTrip> {
+        self.included.iter().find_map(|included| match included {
+            Included::Trip(trip) if trip.id == id => Some(trip),
+            _ => None,
+        })
+    }
+
+    /// Get an included vehicle by ID
+    fn vehicle(&self, id: &str) -> Option<&ApiVehicle> {
+        self.included.iter().find_map(|included| match included {
+            Included::Vehicle(vehicle) if vehicle.id == id => Some(vehicle),
+            _ => None,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Prediction {
+    /// Needed to apply streamed updates
+    id: String,
     attributes: Attributes,
     relationships: Relationships,
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 struct Attributes {
+    /// Empty at the start of the line
+    arrival_time: Option<DateTime<Utc>>,
+    /// Empty at the end of the line
     departure_time: Option<DateTime<Utc>>,
+    #[serde(default)]
+    direction_id: Option<u8>,
+    /// Only set on predictions. `None` for a regularly scheduled trip
+    #[serde(default)]
+    schedule_relationship: Option<ScheduleRelationship>,
+    /// Free-text status, e.g. `Stopped 3 stops away`. Only set on some
+    /// predictions that don't have a time
+    #[serde(default)]
+    status: Option<String>,
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 struct Relationships {
     route: Relationship,
     stop: Relationship,
+    /// Used to match predictions with schedules
+    trip: Option<Relationship>,
+    /// Empty for schedules, and for predictions without a vehicle assigned
+    #[serde(default)]
+    vehicle: Option<Relationship<Option<RelationshipData>>>,
 }
 
-#[derive(Clone, Debug, Deserialize)]
-struct Relationship {
-    data: RelationshipData,
+/// A related resource. Most relationships are required, but some (e.g. a
+/// stop's parent station) can be null
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Relationship<D = RelationshipData> {
+    data: D,
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 struct RelationshipData {
     id: String,
 }
+
+/// A related resource, included in the response via the `include` param.
+/// Resource types we don't use are ignored
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Included {
+    Stop(ApiStop),
+    Trip(ApiTrip),
+    Vehicle(ApiVehicle),
+    #[serde(other)]
+    Other,
+}
+
+impl Included {
+    /// Get the type and ID of the resource, which together identify it
+    fn key(&self) -> Option<(&'static str, &str)> {
+        match self {
+            Self::Stop(stop) => Some(("stop", &stop.id)),
+            Self::Trip(trip) => Some(("trip", &trip.id)),
+            Self::Vehicle(vehicle) => Some(("vehicle", &vehicle.id)),
+            Self::Other => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ApiStop {
+    id: String,
+    relationships: StopRelationships,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct StopRelationships {
+    parent_station: Relationship<Option<RelationshipData>>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ApiTrip {
+    id: String,
+    attributes: TripAttributes,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct TripAttributes {
+    headsign: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ApiVehicle {
+    id: String,
+    attributes: VehicleAttributes,
+    #[serde(default)]
+    relationships: VehicleRelationships,
+}
+
+impl ApiVehicle {
+    /// Get the ID of the stop the vehicle is at or approaching
+    fn stop_id(&self) -> Option<&String> {
+        let stop = self.relationships.stop.as_ref()?.data.as_ref()?;
+        Some(&stop.id)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct VehicleAttributes {
+    current_status: Option<VehicleStatus>,
+    #[serde(default)]
+    occupancy_status: Option<Occupancy>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct VehicleRelationships {
+    #[serde(default)]
+    stop: Option<Relationship<Option<RelationshipData>>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::{
+        FetchResult, FetchedData,
+        http::Recording,
+        test_util::{self, fixture_path},
+    };
+    use serde_json::json;
+
+    /// Build a transit service from the fixture config, with data loaded from
+    /// a recorded response. Return the time of the recording, so countdowns
+    /// are stable
+    fn transit(recording: &str) -> (Transit, DateTime<Utc>) {
+        let config: Config = serde_json::from_reader(
+            std::fs::File::open(fixture_path("config.json")).unwrap(),
+        )
+        .unwrap();
+        let (data, recorded_at) = load_recording(recording);
+        let mut transit = Transit::new(&config);
+        transit.store_result(Ok(FetchedData::new(TransitData::Mbta(data))));
+        (transit, recorded_at)
+    }
+
+    /// Load the body of a recorded response, and the time it was recorded
+    fn load_recording(recording: &str) -> (ApiPredictions, DateTime<Utc>) {
+        let recording = Recording::load(&fixture_path(recording)).unwrap();
+        (
+            serde_json::from_value(recording.body().clone()).unwrap(),
+            recording.recorded_at(),
+        )
+    }
+
+    /// Flatten predictions to (line, [(stop, countdowns)]) for easy comparison
+    fn countdowns(
+        predictions: &Predictions,
+    ) -> Vec<(&str, Vec<(&str, String)>)> {
+        predictions
+            .lines
+            .iter()
+            .map(|line| {
+                (
+                    line.name.as_str(),
+                    line.stops
+                        .iter()
+                        .map(|stop| {
+                            (stop.name.as_str(), stop.predictions.to_string())
+                        })
+                        .collect(),
+                )
+            })
+            .collect()
+    }
+
+    /// A poll that finishes after the stream connects is older than the
+    /// stream's snapshot, so it shouldn't replace it
+    #[test]
+    fn test_stream_drops_polls() {
+        let (expected, now) = transit("regressions/transit_skipped_stop.json");
+        let (mut transit, _) = transit("transit.json");
+        // The fixture config caches to the real cache directory
+        transit.store.cache_path = None;
+        transit.store.in_flight = true;
+        let (streamed, _) =
+            load_recording("regressions/transit_skipped_stop.json");
+        transit.handle_stream(Ok(StreamEvent::Reset(streamed)));
+        // The poll is still running
+        assert!(transit.store.in_flight);
+
+        let (polled, _) = load_recording("transit.json");
+        transit.handle_result(FetchResult {
+            key: transit.cache_key(),
+            result: Ok(FetchedData::new(TransitData::Mbta(polled))),
+        });
+        assert!(!transit.store.in_flight);
+        assert_eq!(
+            countdowns(&transit.predictions_at(None, None, now)),
+            countdowns(&expected.predictions_at(None, None, now))
+        );
+    }
+
+    /// A rebuilt instance needs its own stream, so it gets a fresh reset
+    #[test]
+    fn test_stream_generation() {
+        let (first, _) = transit("transit.json");
+        let (second, _) = transit("transit.json");
+        assert_ne!(first.stream_generation, second.stream_generation);
+    }
+
+    /// Skipped stops, unknown routes, and unknown stops should all be ignored
+    #[test]
+    fn test_predictions_skipped_stop() {
+        let (transit, now) = transit("regressions/transit_skipped_stop.json");
+        assert_eq!(
+            countdowns(&transit.predictions_at(None, None, now)),
+            vec![
+                (
+                    "1",
+                    vec![
+                        ("To Harvard", "3m, 15m, 30m".to_owned()),
+                        ("To Nubian", "21m".to_owned())
+                    ]
+                ),
+                (
+                    "47 / CT2",
+                    vec![
+                        ("To Central", String::new()),
+                        ("To Broadway", String::new())
+                    ]
+                ),
+                (
+                    "Red",
+                    vec![
+                        ("To Alewife", String::new()),
+                        ("To Ashmont/Braintree", String::new())
+                    ]
+                ),
+            ]
+        );
+    }
+
+    /// Predictions for child stops should be grouped under a configured parent
+    /// station, and split by direction
+    #[test]
+    fn test_predictions_parent_station() {
+        let (transit, now) = transit("regressions/transit_parent_station.json");
+        let predictions = transit.predictions_at(None, None, now);
+        assert_eq!(
+            countdowns(&predictions)[2],
+            (
+                "Red",
+                vec![
+                    ("To Alewife", "4m".to_owned()),
+                    ("To Ashmont/Braintree", "2m".to_owned())
+                ]
+            )
+        );
+    }
+
+    /// Multiple routes can share a line, and the display name doesn't need to
+    /// match any route ID
+    #[test]
+    fn test_predictions_shared_stop() {
+        let (transit, now) = transit("regressions/transit_shared_stop.json");
+        let predictions = transit.predictions_at(None, None, now);
+        assert_eq!(
+            countdowns(&predictions)[1],
+            (
+                "47 / CT2",
+                vec![
+                    ("To Central", "7m, 11m".to_owned()),
+                    ("To Broadway", "13m".to_owned())
+                ]
+            )
+        );
+    }
+
+    /// Schedules fill in for trips without a prediction. Trips with a
+    /// prediction, including skipped stops, and past departures are ignored
+    #[test]
+    fn test_predictions_schedules() {
+        let (transit, now) = transit("regressions/transit_skipped_stop.json");
+        let (schedules, _) =
+            load_recording("regressions/transit_schedules.json");
+        assert_eq!(
+            countdowns(&transit.predictions_at(Some(&schedules), None, now)),
+            vec![
+                (
+                    "1",
+                    vec![
+                        ("To Harvard", "3m, 10m skipped, 15m".to_owned()),
+                        ("To Nubian", "~12m, 21m".to_owned())
+                    ]
+                ),
+                (
+                    "47 / CT2",
+                    vec![
+                        ("To Central", "~20m".to_owned()),
+                        ("To Broadway", String::new())
+                    ]
+                ),
+                (
+                    "Red",
+                    vec![
+                        ("To Alewife", String::new()),
+                        ("To Ashmont/Braintree", String::new())
+                    ]
+                ),
+            ]
+        );
+    }
+
+    /// Arrival times should be used at the end of the line or when configured,
+    /// and predictions without a time should show why
+    #[test]
+    fn test_predictions_arrival_status() {
+        let (mut transit, now) =
+            transit("regressions/transit_arrival_status.json");
+        assert_eq!(
+            countdowns(&transit.predictions_at(None, None, now))[..2],
+            [
+                (
+                    "1",
+                    vec![
+                        ("To Harvard", "5m".to_owned()),
+                        ("To Nubian", "6m, Cancelled".to_owned())
+                    ]
+                ),
+                (
+                    "47 / CT2",
+                    vec![
+                        ("To Central", "Stopped 3 stops away".to_owned()),
+                        ("To Broadway", String::new())
+                    ]
+                ),
+            ]
+        );
+
+        transit.lines[0].stops[0].countdown_to = CountdownTo::Arrival;
+        assert_eq!(
+            countdowns(&transit.predictions_at(None, None, now))[0].1[0],
+            ("To Harvard", "4m".to_owned())
+        );
+    }
+
+    /// Vehicles at or approaching the stop replace the countdown, and trip and
+    /// vehicle details are attached to each prediction
+    #[test]
+    fn test_predictions_vehicle_status() {
+        let (transit, now) = transit("regressions/transit_vehicle_status.json");
+        let predictions = transit.predictions_at(None, None, now);
+        assert_eq!(
+            countdowns(&predictions)[..2],
+            [
+                (
+                    "1",
+                    vec![
+                        ("To Harvard", "Boarding, 9m".to_owned()),
+                        ("To Nubian", "Arriving".to_owned())
+                    ]
+                ),
+                (
+                    "47 / CT2",
+                    vec![
+                        ("To Central", "15m".to_owned()),
+                        ("To Broadway", String::new())
+                    ]
+                ),
+            ]
+        );
+
+        let details = |line: usize, stop: usize| {
+            predictions.lines[line].stops[stop]
+                .predictions
+                .iter()
+                .map(|countdown| {
+                    (
+                        countdown.headsign(),
+                        countdown.occupancy().and_then(Occupancy::icon),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            details(0, 0),
+            [(Some("Harvard"), Some("●●○")), (Some("Harvard"), None)]
+        );
+        assert_eq!(details(0, 1), [(Some("Nubian Station"), Some("●○○"))]);
+        assert_eq!(details(1, 0), [(Some("Central Square"), None)]);
+    }
+
+    /// Each stop should show its soonest upcoming departures, up to its own
+    /// limit
+    #[test]
+    fn test_predictions_max() {
+        let (mut transit, now) =
+            transit("regressions/transit_skipped_stop.json");
+        transit.lines[0].stops[0].max_predictions = 4;
+        transit.lines[0].stops[1].max_predictions = 1;
+        let harvard = |now| {
+            countdowns(&transit.predictions_at(None, None, now))[0].1[0]
+                .1
+                .clone()
+        };
+        assert_eq!(harvard(now), "3m, 15m, 30m, 41m");
+        // Departures that have left are dropped. The skipped stop has no time,
+        // so it goes last
+        assert_eq!(
+            harvard(now + TimeDelta::minutes(10)),
+            "5m, 20m, 31m, Skipped"
+        );
+    }
+
+    /// Imminent departures should read `Now` or `Due` rather than `0m` or
+    /// negative values, and departures we can't walk to in time are flagged
+    #[test]
+    fn test_countdown() {
+        let stop: TransitStop = serde_json::from_value(json!({
+            "name": "To Harvard",
+            "id": "2545",
+            "walk_minutes": 5,
+        }))
+        .unwrap();
+        let now = Utc::now();
+        let countdown = |seconds: i64, show_seconds: bool| {
+            Countdown::scheduled(now + TimeDelta::seconds(seconds), now)
+                .for_stop(&stop, show_seconds)
+        };
+
+        assert_eq!(countdown(-90, false).to_string(), "Now");
+        assert_eq!(countdown(20, false).to_string(), "Now");
+        assert_eq!(countdown(45, false).to_string(), "Due");
+        assert_eq!(countdown(272, false).to_string(), "~4m");
+        assert_eq!(countdown(272, true).to_string(), "~4:32");
+        assert_eq!(countdown(605, true).to_string(), "~10:05");
+
+        assert!(countdown(272, false).is_missed());
+        assert!(!countdown(300, false).is_missed());
+    }
+
+    /// Route and direction filters should be pushed into the request when
+    /// possible
+    #[test]
+    fn test_url() {
+        let line = |name: &str, stops: serde_json::Value| {
+            serde_json::from_value::<TransitLine>(json!({
+                "name": name,
+                "stops": stops,
+            }))
+            .unwrap()
+        };
+        let config = |transit_lines: Vec<TransitLine>| Config {
+            transit_lines,
+            ..test_util::config(json!({}))
+        };
+
+        let transit = Transit::new(&config(vec![
+            line("1", json!([{"name": "A", "id": "1", "direction_id": 0}])),
+            line("Red", json!([{"name": "B", "id": "2", "direction_id": 0}])),
+        ]));
+        assert_eq!(
+            transit.url,
+            "https://api-v3.mbta.com/predictions?filter[stop]=1,2\
+            &filter[route]=1,Red&include=stop,trip,vehicle\
+            &filter[direction_id]=0\
+            &fields[prediction]=arrival_time,departure_time,direction_id,\
+            schedule_relationship,status\
+            &fields[trip]=headsign\
+            &fields[vehicle]=current_status,occupancy_status\
+            &sort=departure_time"
+        );
+
+        // Mixed directions are filtered locally instead
+        let transit = Transit::new(&config(vec![line(
+            "1",
+            json!([
+                {"name": "A", "id": "1", "direction_id": 0},
+                {"name": "B", "id": "1", "direction_id": 1},
+            ]),
+        )]));
+        assert_eq!(
+            transit.url,
+            "https://api-v3.mbta.com/predictions?filter[stop]=1\
+            &filter[route]=1&include=stop,trip,vehicle\
+            &fields[prediction]=arrival_time,departure_time,direction_id,\
+            schedule_relationship,status\
+            &fields[trip]=headsign\
+            &fields[vehicle]=current_status,occupancy_status\
+            &sort=departure_time"
+        );
+    }
+}
@@ -0,0 +1,171 @@
+use crate::{
+    Message,
+    config::Config,
+    services::{
+        DataStore, ExternalData, FetchResult,
+        http::fetch_json,
+        transit::{API_HOST, api_key, api_url, stop_route_params},
+    },
+};
+use anyhow::Context;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Active service alerts (shuttles, suspensions, delays, etc.) for the
+/// configured routes and stops
+#[derive(Debug)]
+pub struct Alerts {
+    url: String,
+    api_key: Option<String>,
+    /// Alerts come from the MBTA API. GTFS-Realtime alert feeds aren't
+    /// supported
+    enabled: bool,
+    store: DataStore<ApiAlerts>,
+}
+
+impl Alerts {
+    /// Alert attributes that we use
+    const ALERT_FIELDS: &[&str] = &["header", "effect", "severity"];
+
+    pub fn new(config: &Config) -> Self {
+        let mut params = stop_route_params(&config.transit_lines);
+        params.extend([
+            // Only include alerts that are in effect right now
+            ("filter[datetime]", "NOW".to_owned()),
+            ("fields[alert]", Self::ALERT_FIELDS.join(",")),
+            ("sort", "-severity".to_owned()),
+        ]);
+        Self {
+            url: api_url("alerts", &params),
+            api_key: api_key(config),
+            enabled: config.transit_provider.is_mbta(),
+            store: DataStore::new(
+                config.refresh.schedule::<Self>(),
+                config.cache_path::<Self>(),
+            ),
+        }
+    }
+
+    /// Get all active alerts, most severe first
+    pub fn alerts(&self) -> &[Alert] {
+        self.data()
+            .map(|data| data.data.data.as_slice())
+            .unwrap_or(&[])
+    }
+}
+
+impl ExternalData for Alerts {
+    const NAME: &'static str = "alerts";
+    const DEFAULT_TTL: Duration = Duration::from_secs(2 * 60);
+    const RATE_LIMITED_HOST: Option<&'static str> = Some(API_HOST);
+    type Data = ApiAlerts;
+
+    fn same_settings(&self, other: &Self) -> bool {
+        self.url == other.url
+            && self.api_key == other.api_key
+            && self.enabled == other.enabled
+            && self.store.refresh == other.store.refresh
+    }
+
+    fn cache_key(&self) -> String {
+        self.url.clone()
+    }
+
+    fn store(&self) -> &DataStore<Self::Data> {
+        &self.store
+    }
+
+    fn store_mut(&mut self) -> &mut DataStore<Self::Data> {
+        &mut self.store
+    }
+
+    fn result_to_message(result: FetchResult<Self::Data>) -> Message {
+        Message::AlertsFetched(result)
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn fetch(
+        &self,
+    ) -> impl 'static + Future<Output = anyhow::Result<Self::Data>> + Send {
+        info!("Fetching alert data from {}", self.url);
+        let request = super::request(&self.url, self.api_key.as_deref());
+        async move {
+            fetch_json(Self::NAME, request)
+                .await
+                .context("Error fetching alerts")
+        }
+    }
+}
+
+/// <https://api-v3.mbta.com/docs/swagger/index.html#/Alert/ApiWeb_AlertController_index>
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ApiAlerts {
+    data: Vec<Alert>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Alert {
+    attributes: AlertAttributes,
+}
+
+impl Alert {
+    /// Alerts at or above this severity get extra attention. The MBTA uses
+    /// the same cutoff on its own site
+    const SEVERE: u8 = 7;
+
+    /// Short summary of the alert
+    pub fn header(&self) -> &str {
+        &self.attributes.header
+    }
+
+    /// Human-readable effect on service, e.g. `Station closure`
+    pub fn effect(&self) -> String {
+        // The API gives e.g. STATION_CLOSURE
+        let effect = self.attributes.effect.replace('_', " ").to_lowercase();
+        let mut chars = effect.chars();
+        chars
+            .next()
+            .map(|first| first.to_uppercase().chain(chars).collect())
+            .unwrap_or_default()
+    }
+
+    /// Severity from 0 (least) to 10 (most)
+    pub fn severity(&self) -> u8 {
+        self.attributes.severity
+    }
+
+    pub fn is_severe(&self) -> bool {
+        self.severity() >= Self::SEVERE
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct AlertAttributes {
+    header: String,
+    effect: String,
+    severity: u8,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_alert() {
+        let alert: Alert = serde_json::from_value(json!({
+            "attributes": {
+                "header": "Shuttle buses replace Red Line service",
+                "effect": "STATION_CLOSURE",
+                "severity": 7,
+            },
+        }))
+        .unwrap();
+        assert_eq!(alert.effect(), "Station closure");
+        assert!(alert.is_severe());
+    }
+}
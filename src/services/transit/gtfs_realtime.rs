@@ -0,0 +1,381 @@
+//! Realtime predictions from any agency that publishes a GTFS-Realtime
+//! TripUpdates feed. Feeds are protobuf, and don't include names or parent
+//! stations, so they're paired with the agency's static GTFS feed.
+//! <https://gtfs.org/documentation/realtime/reference/>
+
+use crate::services::transit::{
+    Departure, ScheduleRelationship, TransitLine, gtfs_static::GtfsStaticData,
+};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// Trip updates from a GTFS-Realtime feed. This is a trimmed down version of
+/// the protobuf message, so it can be cached as JSON
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GtfsRealtimeData {
+    trips: Vec<TripUpdate>,
+}
+
+impl GtfsRealtimeData {
+    /// Decode a protobuf `FeedMessage`. Feeds cover the whole agency, so only
+    /// trips on the configured routes are kept. Trips that don't list a route
+    /// are kept, since their route may be in the static feed
+    pub fn decode(body: &[u8], lines: &[TransitLine]) -> anyhow::Result<Self> {
+        let message = proto::FeedMessage::decode(body)
+            .context("Error decoding GTFS-Realtime feed")?;
+        let trips = message
+            .entity
+            .into_iter()
+            .filter(|entity| !entity.is_deleted())
+            .filter_map(|entity| entity.trip_update)
+            .filter_map(TripUpdate::from_proto)
+            .filter(|trip| {
+                trip.route_id.as_ref().is_none_or(|route_id| {
+                    lines.iter().any(|line| line.has_route(route_id))
+                })
+            })
+            .collect();
+        Ok(Self { trips })
+    }
+
+    /// Get a departure for every configured stop of every trip. Trips list
+    /// every stop along the route, so stops that aren't configured (directly
+    /// or through their parent station) are skipped. Route, direction, and
+    /// headsign are filled in from the static feed where the realtime feed
+    /// doesn't have them. Trips without a known route are skipped
+    pub(super) fn departures<'a>(
+        &'a self,
+        lines: &[TransitLine],
+        gtfs_static: Option<&'a GtfsStaticData>,
+    ) -> Vec<Departure<'a>> {
+        let configured_stops: HashSet<&str> = lines
+            .iter()
+            .flat_map(|line| &line.stops)
+            .map(|stop| stop.id.as_str())
+            .collect();
+        self.trips
+            .iter()
+            .flat_map(|trip| {
+                let static_trip =
+                    trip.trip_id.as_ref().zip(gtfs_static).and_then(
+                        |(trip_id, gtfs_static)| gtfs_static.trip(trip_id),
+                    );
+                let route_id = trip.route_id.as_deref().or_else(|| {
+                    static_trip.map(|static_trip| static_trip.route_id.as_str())
+                });
+                let direction_id = trip.direction_id.or_else(|| {
+                    static_trip.and_then(|static_trip| static_trip.direction_id)
+                });
+                let configured_stops = &configured_stops;
+                trip.stops.iter().filter_map(move |stop| {
+                    // The static feed only has parents that are configured
+                    let parent_station_id =
+                        gtfs_static.and_then(|gtfs_static| {
+                            gtfs_static.parent_station(&stop.stop_id)
+                        });
+                    if parent_station_id.is_none()
+                        && !configured_stops.contains(stop.stop_id.as_str())
+                    {
+                        return None;
+                    }
+                    Some(Departure {
+                        trip_id: trip.trip_id.as_deref(),
+                        route_id: route_id?,
+                        stop_id: &stop.stop_id,
+                        parent_station_id,
+                        direction_id,
+                        arrival_time: stop.arrival_time,
+                        departure_time: stop.departure_time,
+                        // A cancelled trip skips every stop, so that takes
+                        // precedence over the stop's relationship
+                        schedule_relationship: match trip.schedule_relationship
+                        {
+                            Some(ScheduleRelationship::Cancelled) => {
+                                trip.schedule_relationship
+                            }
+                            _ => stop
+                                .schedule_relationship
+                                .or(trip.schedule_relationship),
+                        },
+                        status: None,
+                        headsign: static_trip.and_then(|static_trip| {
+                            static_trip.headsign.as_deref()
+                        }),
+                        vehicle_status: None,
+                        occupancy: None,
+                    })
+                })
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct TripUpdate {
+    trip_id: Option<String>,
+    route_id: Option<String>,
+    direction_id: Option<u8>,
+    schedule_relationship: Option<ScheduleRelationship>,
+    stops: Vec<StopTimeUpdate>,
+}
+
+impl TripUpdate {
+    /// Convert from protobuf. `None` if the trip should be hidden
+    fn from_proto(trip_update: proto::TripUpdate) -> Option<Self> {
+        use proto::TripScheduleRelationship as Relationship;
+
+        let trip = trip_update.trip.unwrap_or_default();
+        let schedule_relationship = match trip.schedule_relationship() {
+            // Deleted trips shouldn't be shown at all
+            Relationship::Deleted => return None,
+            Relationship::Canceled => Some(ScheduleRelationship::Cancelled),
+            Relationship::Added
+            | Relationship::Replacement
+            | Relationship::Duplicated => Some(ScheduleRelationship::Added),
+            Relationship::Unscheduled => {
+                Some(ScheduleRelationship::Unscheduled)
+            }
+            Relationship::Scheduled => None,
+        };
+        Some(Self {
+            trip_id: trip.trip_id,
+            route_id: trip.route_id,
+            direction_id: trip.direction_id.map(|id| id as u8),
+            schedule_relationship,
+            stops: trip_update
+                .stop_time_update
+                .into_iter()
+                .filter_map(StopTimeUpdate::from_proto)
+                .collect(),
+        })
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct StopTimeUpdate {
+    stop_id: String,
+    arrival_time: Option<DateTime<Utc>>,
+    departure_time: Option<DateTime<Utc>>,
+    schedule_relationship: Option<ScheduleRelationship>,
+}
+
+impl StopTimeUpdate {
+    /// Convert from protobuf. `None` if the update doesn't identify its stop.
+    /// Updates can give a delay relative to the static schedule instead of an
+    /// absolute time, but we don't load stop times from the static feed, so
+    /// those are treated as having no time
+    fn from_proto(update: proto::StopTimeUpdate) -> Option<Self> {
+        use proto::StopScheduleRelationship as Relationship;
+
+        let time = |event: Option<proto::StopTimeEvent>| {
+            DateTime::from_timestamp(event?.time?, 0)
+        };
+        let schedule_relationship = match update.schedule_relationship() {
+            Relationship::Skipped => Some(ScheduleRelationship::Skipped),
+            Relationship::NoData => Some(ScheduleRelationship::NoData),
+            Relationship::Unscheduled => {
+                Some(ScheduleRelationship::Unscheduled)
+            }
+            Relationship::Scheduled => None,
+        };
+        Some(Self {
+            stop_id: update.stop_id?,
+            arrival_time: time(update.arrival),
+            departure_time: time(update.departure),
+            schedule_relationship,
+        })
+    }
+}
+
+/// Protobuf messages from `gtfs-realtime.proto`, with only the fields we use.
+/// Unknown fields are skipped when decoding
+mod proto {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct FeedMessage {
+        #[prost(message, repeated, tag = "2")]
+        pub entity: Vec<FeedEntity>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct FeedEntity {
+        #[prost(bool, optional, tag = "2")]
+        pub is_deleted: Option<bool>,
+        #[prost(message, optional, tag = "3")]
+        pub trip_update: Option<TripUpdate>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct TripUpdate {
+        #[prost(message, optional, tag = "1")]
+        pub trip: Option<TripDescriptor>,
+        #[prost(message, repeated, tag = "2")]
+        pub stop_time_update: Vec<StopTimeUpdate>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct TripDescriptor {
+        #[prost(string, optional, tag = "1")]
+        pub trip_id: Option<String>,
+        #[prost(enumeration = "TripScheduleRelationship", optional, tag = "4")]
+        pub schedule_relationship: Option<i32>,
+        #[prost(string, optional, tag = "5")]
+        pub route_id: Option<String>,
+        #[prost(uint32, optional, tag = "6")]
+        pub direction_id: Option<u32>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct StopTimeUpdate {
+        #[prost(message, optional, tag = "2")]
+        pub arrival: Option<StopTimeEvent>,
+        #[prost(message, optional, tag = "3")]
+        pub departure: Option<StopTimeEvent>,
+        #[prost(string, optional, tag = "4")]
+        pub stop_id: Option<String>,
+        #[prost(enumeration = "StopScheduleRelationship", optional, tag = "5")]
+        pub schedule_relationship: Option<i32>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct StopTimeEvent {
+        /// Unix timestamp
+        #[prost(int64, optional, tag = "2")]
+        pub time: Option<i64>,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, prost::Enumeration)]
+    #[repr(i32)]
+    pub enum TripScheduleRelationship {
+        Scheduled = 0,
+        Added = 1,
+        Unscheduled = 2,
+        Canceled = 3,
+        Replacement = 5,
+        Duplicated = 6,
+        Deleted = 7,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, prost::Enumeration)]
+    #[repr(i32)]
+    pub enum StopScheduleRelationship {
+        Scheduled = 0,
+        Skipped = 1,
+        NoData = 2,
+        Unscheduled = 3,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::{
+        ExternalData, FetchedData,
+        test_util::{config, fixture_path},
+        transit::{Transit, TransitData},
+    };
+    use serde_json::json;
+
+    /// Realtime predictions should be matched to configured stops through
+    /// the static feed, the same as MBTA predictions
+    #[test]
+    fn test_predictions() {
+        let config = config(json!({
+            "transit_provider": {
+                "type": "gtfs_realtime",
+                "trip_updates_url": "https://example.com/trip_updates.pb",
+                "static_url": "https://example.com/gtfs.zip",
+            },
+            "transit_lines": [{
+                "name": "10",
+                "stops": [
+                    {"name": "Northbound", "id": "station-a"},
+                    {"name": "Southbound", "id": "stop-b"},
+                ],
+            }],
+        }));
+        let gtfs_static = GtfsStaticData::parse(
+            &std::fs::read(fixture_path("gtfs/static.zip")).unwrap(),
+            &config.transit_lines,
+        )
+        .unwrap();
+        let feed = GtfsRealtimeData::decode(
+            &std::fs::read(fixture_path("gtfs/trip_updates.pb")).unwrap(),
+            &config.transit_lines,
+        )
+        .unwrap();
+        // Deleted trips and other routes are dropped
+        assert_eq!(feed.trips.len(), 3);
+
+        let mut transit = Transit::new(&config);
+        transit.store_result(Ok(FetchedData::new(TransitData::GtfsRealtime(
+            feed,
+        ))));
+        let now = DateTime::parse_from_rfc3339("2025-06-12T12:00:00Z")
+            .unwrap()
+            .to_utc();
+        let predictions = transit.predictions_at(None, Some(&gtfs_static), now);
+        let stops = &predictions.lines[0].stops;
+        assert_eq!(stops[0].predictions.to_string(), "5m, Cancelled, Skipped");
+        assert_eq!(
+            stops[0].predictions.iter().next().unwrap().headsign(),
+            Some("Downtown")
+        );
+        // End of the line, so the arrival time is used
+        assert_eq!(stops[1].predictions.to_string(), "2m");
+    }
+
+    /// Trips list every stop on the route, but only configured stops and
+    /// children of configured stations should become departures
+    #[test]
+    fn test_departures_configured_stops() {
+        let lines: Vec<TransitLine> = serde_json::from_value(json!([{
+            "name": "10",
+            "stops": [
+                {"name": "Northbound", "id": "station-a"},
+                {"name": "Southbound", "id": "stop-b"},
+            ],
+        }]))
+        .unwrap();
+        let gtfs_static = GtfsStaticData::parse(
+            &std::fs::read(fixture_path("gtfs/static.zip")).unwrap(),
+            &lines,
+        )
+        .unwrap();
+        let stop = |stop_id: &str| proto::StopTimeUpdate {
+            stop_id: Some(stop_id.to_owned()),
+            departure: Some(proto::StopTimeEvent {
+                time: Some(1_749_729_600),
+            }),
+            ..proto::StopTimeUpdate::default()
+        };
+        let message = proto::FeedMessage {
+            entity: vec![proto::FeedEntity {
+                is_deleted: None,
+                trip_update: Some(proto::TripUpdate {
+                    trip: Some(proto::TripDescriptor {
+                        trip_id: Some("trip-1".to_owned()),
+                        route_id: Some("10".to_owned()),
+                        ..proto::TripDescriptor::default()
+                    }),
+                    stop_time_update: vec![
+                        stop("a-1"),
+                        stop("elsewhere"),
+                        stop("stop-b"),
+                    ],
+                }),
+            }],
+        };
+        let feed =
+            GtfsRealtimeData::decode(&message.encode_to_vec(), &lines).unwrap();
+
+        let stops: Vec<_> = feed
+            .departures(&lines, Some(&gtfs_static))
+            .iter()
+            .map(|departure| departure.stop_id)
+            .collect();
+        assert_eq!(stops, ["a-1", "stop-b"]);
+    }
+}
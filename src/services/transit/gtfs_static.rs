@@ -0,0 +1,274 @@
+//! Static GTFS feed, used to fill in what GTFS-Realtime feeds leave out:
+//! parent stations, and each trip's route, direction, and headsign.
+//! <https://gtfs.org/documentation/schedule/reference/>
+
+use crate::{
+    Message,
+    config::Config,
+    services::{
+        CLIENT, DataStore, ExternalData, FetchResult,
+        http::fetch,
+        transit::{TransitLine, TransitProvider, lines_key},
+    },
+};
+use anyhow::Context;
+use log::info;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use std::{
+    collections::HashMap,
+    io::{Cursor, Read},
+    time::Duration,
+};
+use zip::ZipArchive;
+
+/// The static GTFS feed for the configured GTFS-Realtime provider. Disabled
+/// for other providers
+#[derive(Debug)]
+pub struct GtfsStatic {
+    /// URL of the feed's zip file. `None` if the provider doesn't use one
+    url: Option<String>,
+    lines: Vec<TransitLine>,
+    store: DataStore<GtfsStaticData>,
+}
+
+impl GtfsStatic {
+    pub fn new(config: &Config) -> Self {
+        let url = match &config.transit_provider {
+            TransitProvider::Mbta => None,
+            TransitProvider::GtfsRealtime { static_url, .. } => {
+                Some(static_url.clone())
+            }
+        };
+        Self {
+            url,
+            lines: config.transit_lines.clone(),
+            store: DataStore::new(
+                config.refresh.schedule::<Self>(),
+                config.cache_path::<Self>(),
+            ),
+        }
+    }
+}
+
+impl ExternalData for GtfsStatic {
+    const NAME: &'static str = "gtfs_static";
+    // Static feeds are typically published once a day at most
+    const DEFAULT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+    type Data = GtfsStaticData;
+
+    fn same_settings(&self, other: &Self) -> bool {
+        self.url == other.url
+            && self.lines == other.lines
+            && self.store.refresh == other.store.refresh
+    }
+
+    fn cache_key(&self) -> String {
+        // Stops and trips are filtered by the lines while parsing
+        format!(
+            "{}#{}",
+            self.url.as_deref().unwrap_or_default(),
+            lines_key(&self.lines)
+        )
+    }
+
+    fn store(&self) -> &DataStore<Self::Data> {
+        &self.store
+    }
+
+    fn store_mut(&mut self) -> &mut DataStore<Self::Data> {
+        &mut self.store
+    }
+
+    fn result_to_message(result: FetchResult<Self::Data>) -> Message {
+        Message::GtfsStaticFetched(result)
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.url.is_some()
+    }
+
+    fn fetch(
+        &self,
+    ) -> impl 'static + Future<Output = anyhow::Result<Self::Data>> + Send {
+        let url = self.url.clone().unwrap_or_default();
+        info!("Fetching static GTFS feed from {url}");
+        let request = CLIENT.get(&url);
+        let lines = self.lines.clone();
+        async move {
+            let response = fetch(Self::NAME, request).await?;
+            GtfsStaticData::parse(&response.body, &lines)
+                .context("Error fetching static GTFS feed")
+        }
+    }
+}
+
+/// The parts of a static GTFS feed that we use. Feeds cover the whole agency
+/// and can be huge, so only stops and trips relevant to the configured lines
+/// are kept
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct GtfsStaticData {
+    /// Parent station ID of each configured stop's children
+    parent_stations: HashMap<String, String>,
+    trips: HashMap<String, StaticTrip>,
+}
+
+impl GtfsStaticData {
+    /// Parse a zipped feed
+    pub(super) fn parse(
+        zip: &[u8],
+        lines: &[TransitLine],
+    ) -> anyhow::Result<Self> {
+        let mut archive = ZipArchive::new(Cursor::new(zip))
+            .context("Error opening static GTFS zip")?;
+
+        let configured_stops: Vec<&str> = lines
+            .iter()
+            .flat_map(|line| &line.stops)
+            .map(|stop| stop.id.as_str())
+            .collect();
+        let parent_stations = read_csv::<StopRow>(&mut archive, "stops.txt")?
+            .into_iter()
+            .filter_map(|stop| Some((stop.stop_id, stop.parent_station?)))
+            .filter(|(_, parent)| configured_stops.contains(&parent.as_str()))
+            .collect();
+
+        let trips = read_csv::<TripRow>(&mut archive, "trips.txt")?
+            .into_iter()
+            .filter(|trip| {
+                lines.iter().any(|line| line.has_route(&trip.route_id))
+            })
+            .map(|trip| {
+                (
+                    trip.trip_id,
+                    StaticTrip {
+                        route_id: trip.route_id,
+                        direction_id: trip.direction_id,
+                        headsign: trip.trip_headsign,
+                    },
+                )
+            })
+            .collect();
+        Ok(Self {
+            parent_stations,
+            trips,
+        })
+    }
+
+    /// Get the parent station of a stop, if it has one that's configured
+    pub(super) fn parent_station(&self, stop_id: &str) -> Option<&str> {
+        self.parent_stations.get(stop_id).map(String::as_str)
+    }
+
+    /// Get a trip on one of the configured routes
+    pub(super) fn trip(&self, trip_id: &str) -> Option<&StaticTrip> {
+        self.trips.get(trip_id)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(super) struct StaticTrip {
+    pub route_id: String,
+    pub direction_id: Option<u8>,
+    /// Destination shown on the vehicle, e.g. `Harvard`
+    pub headsign: Option<String>,
+}
+
+/// A row of `stops.txt`. Unused columns are ignored
+#[derive(Debug, Deserialize)]
+struct StopRow {
+    stop_id: String,
+    #[serde(default)]
+    parent_station: Option<String>,
+}
+
+/// A row of `trips.txt`. Unused columns are ignored
+#[derive(Debug, Deserialize)]
+struct TripRow {
+    trip_id: String,
+    route_id: String,
+    #[serde(default)]
+    direction_id: Option<u8>,
+    #[serde(default)]
+    trip_headsign: Option<String>,
+}
+
+/// Read all rows of a CSV file in the feed
+fn read_csv<T: DeserializeOwned>(
+    archive: &mut ZipArchive<Cursor<&[u8]>>,
+    name: &str,
+) -> anyhow::Result<Vec<T>> {
+    let mut file = archive
+        .by_name(name)
+        .context(format!("Static GTFS feed is missing `{name}`"))?;
+    let mut contents = Vec::new();
+    file.read_to_end(&mut contents)
+        .context(format!("Error reading `{name}`"))?;
+    csv::Reader::from_reader(contents.as_slice())
+        .deserialize()
+        .collect::<Result<_, _>>()
+        .context(format!("Error parsing `{name}`"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::test_util::{config, fixture_path};
+    use serde_json::json;
+
+    #[test]
+    fn test_parse() {
+        let zip = std::fs::read(fixture_path("gtfs/static.zip")).unwrap();
+        let lines: Vec<TransitLine> = serde_json::from_value(json!([{
+            "name": "10",
+            "stops": [{"name": "Northbound", "id": "station-a"}],
+        }]))
+        .unwrap();
+        let data = GtfsStaticData::parse(&zip, &lines).unwrap();
+
+        // Only children of configured stations are kept
+        assert_eq!(data.parent_station("a-1"), Some("station-a"));
+        assert_eq!(data.parent_station("x-1"), None);
+        // Only trips on configured routes are kept
+        let trip = data.trip("trip-3").unwrap();
+        assert_eq!(trip.route_id, "10");
+        assert_eq!(trip.direction_id, Some(1));
+        assert_eq!(trip.headsign.as_deref(), Some("Uptown"));
+        assert!(data.trip("trip-9").is_none());
+    }
+
+    /// Any change to the stops or routes that the data is filtered by should
+    /// invalidate the cache, even if the line keeps its name
+    #[test]
+    fn test_cache_key() {
+        let gtfs_static = |lines: serde_json::Value| {
+            GtfsStatic::new(&config(json!({
+                "transit_provider": {
+                    "type": "gtfs_realtime",
+                    "trip_updates_url": "https://example.com/trip_updates.pb",
+                    "static_url": "https://example.com/gtfs.zip",
+                },
+                "transit_lines": lines,
+            })))
+            .cache_key()
+        };
+        let key = gtfs_static(json!([{
+            "name": "10",
+            "stops": [{"name": "A", "id": "station-a"}],
+        }]));
+        assert_eq!(key, "https://example.com/gtfs.zip#10@station-a");
+        for lines in [
+            json!([{"name": "10", "stops": [{"name": "A", "id": "b"}]}]),
+            json!([{
+                "name": "10",
+                "routes": ["11"],
+                "stops": [{"name": "A", "id": "station-a"}],
+            }]),
+            json!([{
+                "name": "10",
+                "stops": [{"name": "A", "id": "station-a", "direction_id": 0}],
+            }]),
+        ] {
+            assert_ne!(gtfs_static(lines), key);
+        }
+    }
+}
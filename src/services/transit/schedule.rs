@@ -0,0 +1,96 @@
+use crate::{
+    Message,
+    config::Config,
+    services::{
+        DataStore, ExternalData, FetchResult,
+        http::fetch_json,
+        transit::{API_HOST, ApiPredictions, api_key, api_url, filter_params},
+    },
+};
+use anyhow::Context;
+use log::info;
+use std::time::Duration;
+
+/// Scheduled departures for the configured stops. These fill in for trips that
+/// don't have a realtime prediction, e.g. late at night or on commuter rail,
+/// so that an empty stop means there's no service.
+#[derive(Debug)]
+pub struct Schedules {
+    url: String,
+    api_key: Option<String>,
+    /// GTFS-Realtime providers have no schedule endpoint, so this is only
+    /// enabled for the MBTA
+    enabled: bool,
+    /// Schedules have the same shape as predictions, so they share a type
+    store: DataStore<ApiPredictions>,
+}
+
+impl Schedules {
+    /// Schedule attributes that we use
+    const SCHEDULE_FIELDS: &[&str] =
+        &["arrival_time", "departure_time", "direction_id"];
+
+    pub fn new(config: &Config) -> Self {
+        let mut params = filter_params(&config.transit_lines, &["stop"]);
+        params.extend([
+            ("fields[schedule]", Self::SCHEDULE_FIELDS.join(",")),
+            ("sort", "departure_time".to_owned()),
+        ]);
+        Self {
+            url: api_url("schedules", &params),
+            api_key: api_key(config),
+            enabled: config.transit_provider.is_mbta(),
+            store: DataStore::new(
+                config.refresh.schedule::<Self>(),
+                config.cache_path::<Self>(),
+            ),
+        }
+    }
+}
+
+impl ExternalData for Schedules {
+    const NAME: &'static str = "schedules";
+    // Schedules rarely change during the day
+    const DEFAULT_TTL: Duration = Duration::from_secs(15 * 60);
+    const RATE_LIMITED_HOST: Option<&'static str> = Some(API_HOST);
+    type Data = ApiPredictions;
+
+    fn same_settings(&self, other: &Self) -> bool {
+        self.url == other.url
+            && self.api_key == other.api_key
+            && self.enabled == other.enabled
+            && self.store.refresh == other.store.refresh
+    }
+
+    fn cache_key(&self) -> String {
+        self.url.clone()
+    }
+
+    fn store(&self) -> &DataStore<Self::Data> {
+        &self.store
+    }
+
+    fn store_mut(&mut self) -> &mut DataStore<Self::Data> {
+        &mut self.store
+    }
+
+    fn result_to_message(result: FetchResult<Self::Data>) -> Message {
+        Message::SchedulesFetched(result)
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn fetch(
+        &self,
+    ) -> impl 'static + Future<Output = anyhow::Result<Self::Data>> + Send {
+        info!("Fetching schedule data from {}", self.url);
+        let request = super::request(&self.url, self.api_key.as_deref());
+        async move {
+            fetch_json(Self::NAME, request)
+                .await
+                .context("Error fetching schedules")
+        }
+    }
+}
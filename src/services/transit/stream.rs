@@ -0,0 +1,297 @@
+//! Stream prediction updates from the MBTA API, instead of polling for the
+//! full set of predictions.
+//! <https://www.mbta.com/developers/v3-api/streaming>
+
+use crate::{
+    Message,
+    services::{
+        FetchError, RetryPolicy, STREAM_CLIENT,
+        http::{EventStreamParser, ServerSentEvent},
+        transit::{
+            self, ApiPredictions, ApiStop, ApiTrip, ApiVehicle, Included,
+            Prediction,
+        },
+    },
+};
+use anyhow::{Context, anyhow};
+use iced::futures::{SinkExt, Stream, channel::mpsc};
+use log::{info, warn};
+use reqwest::header;
+use serde::Deserialize;
+use std::{convert::Infallible, time::Duration};
+
+/// If nothing is received for this long, assume the connection is dead and
+/// reconnect. The API sends keep-alives when there are no updates, so this
+/// only fires on a dead connection. Data isn't marked as stale while
+/// streaming, so this bounds how long a dead stream can pass as live
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Schedule for reconnecting after the stream drops. Polling fills in while
+/// we're disconnected, so there's no rush
+const RECONNECT_POLICY: RetryPolicy = RetryPolicy {
+    initial: Duration::from_secs(10),
+    max: Duration::from_secs(10 * 60),
+};
+
+/// Connect to the prediction stream and emit its events as messages. If the
+/// connection drops, an error is emitted so the service can fall back to
+/// polling, then we reconnect after a delay. The stream never ends on its own.
+pub fn connect(
+    url: String,
+    api_key: Option<String>,
+) -> impl Stream<Item = Message> {
+    iced::stream::channel(100, move |mut output| async move {
+        let mut failures = 0;
+        loop {
+            let Err(error) =
+                run(&url, api_key.as_deref(), &mut output, &mut failures).await;
+            failures += 1;
+            let delay = RECONNECT_POLICY.delay(failures);
+            warn!(
+                "Transit stream dropped, reconnecting in {delay:?}: {error:#}"
+            );
+            let message = Message::TransitStreamed(Err(FetchError::new(error)));
+            if output.send(message).await.is_err() {
+                // Subscription was dropped
+                return;
+            }
+            tokio::time::sleep(delay).await;
+        }
+    })
+}
+
+/// Run a single connection until it fails. The failure count is reset once
+/// the connection is established
+async fn run(
+    url: &str,
+    api_key: Option<&str>,
+    output: &mut mpsc::Sender<Message>,
+    failures: &mut u32,
+) -> anyhow::Result<Infallible> {
+    info!("Connecting to transit stream {url}");
+    let mut response = transit::authorize(STREAM_CLIENT.get(url), api_key)
+        .header(header::ACCEPT, "text/event-stream")
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .context("Error connecting to transit stream")?;
+    let mut parser = EventStreamParser::default();
+    loop {
+        let chunk = tokio::time::timeout(IDLE_TIMEOUT, response.chunk())
+            .await
+            .map_err(|_| anyhow!("No data received for {IDLE_TIMEOUT:?}"))?
+            .context("Error reading transit stream")?
+            .context("Transit stream closed by server")?;
+        for event in parser.push(&chunk) {
+            let Some(event) = StreamEvent::parse(&event).context(format!(
+                "Error parsing transit stream `{}` event",
+                event.event
+            ))?
+            else {
+                continue;
+            };
+            if let StreamEvent::Reset(_) = event {
+                info!("Connected to transit stream");
+                *failures = 0;
+            }
+            output.send(Message::TransitStreamed(Ok(event))).await?;
+        }
+    }
+}
+
+/// An update from the prediction stream
+#[derive(Debug)]
+pub enum StreamEvent {
+    /// Replace all data. This is sent first after connecting
+    Reset(ApiPredictions),
+    /// Add a new resource, or replace an existing one with the same ID
+    Upsert(Resource),
+    /// Remove a resource
+    Remove(ResourceId),
+}
+
+impl StreamEvent {
+    /// Parse a server-sent event. `None` if it's a type we don't handle
+    fn parse(event: &ServerSentEvent) -> anyhow::Result<Option<Self>> {
+        let event = match event.event.as_str() {
+            "reset" => Self::Reset(ApiPredictions::from_resources(
+                serde_json::from_str(&event.data)?,
+            )),
+            "add" | "update" => {
+                Self::Upsert(serde_json::from_str(&event.data)?)
+            }
+            "remove" => Self::Remove(serde_json::from_str(&event.data)?),
+            other => {
+                warn!("Ignoring unknown transit stream event `{other}`");
+                return Ok(None);
+            }
+        };
+        Ok(Some(event))
+    }
+}
+
+/// A resource sent by the stream. Predictions and their included resources
+/// are mixed together
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Resource {
+    Prediction(Prediction),
+    Stop(ApiStop),
+    Trip(ApiTrip),
+    Vehicle(ApiVehicle),
+    #[serde(other)]
+    Other,
+}
+
+/// Identifies a resource to remove
+#[derive(Debug, Deserialize)]
+pub struct ResourceId {
+    id: String,
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+impl ApiPredictions {
+    /// Split a list of mixed resources into predictions and included
+    /// resources
+    fn from_resources(resources: Vec<Resource>) -> Self {
+        let mut predictions = Self {
+            data: Vec::new(),
+            included: Vec::new(),
+        };
+        for resource in resources {
+            predictions.upsert(resource);
+        }
+        predictions.sort();
+        predictions
+    }
+
+    /// Apply an incremental update from the stream
+    pub(super) fn apply(&mut self, event: StreamEvent) {
+        match event {
+            StreamEvent::Reset(predictions) => *self = predictions,
+            StreamEvent::Upsert(resource) => {
+                self.upsert(resource);
+                self.sort();
+            }
+            StreamEvent::Remove(ResourceId { id, kind }) => {
+                if kind == "prediction" {
+                    self.data.retain(|prediction| prediction.id != id);
+                } else {
+                    let key = Some((kind.as_str(), id.as_str()));
+                    self.included.retain(|included| included.key() != key);
+                }
+            }
+        }
+    }
+
+    fn upsert(&mut self, resource: Resource) {
+        let included = match resource {
+            Resource::Prediction(prediction) => {
+                let existing = self
+                    .data
+                    .iter_mut()
+                    .find(|other| other.id == prediction.id);
+                match existing {
+                    Some(other) => *other = prediction,
+                    None => self.data.push(prediction),
+                }
+                return;
+            }
+            Resource::Stop(stop) => Included::Stop(stop),
+            Resource::Trip(trip) => Included::Trip(trip),
+            Resource::Vehicle(vehicle) => Included::Vehicle(vehicle),
+            Resource::Other => return,
+        };
+        let key = included.key();
+        match self.included.iter_mut().find(|other| other.key() == key) {
+            Some(other) => *other = included,
+            None => self.included.push(included),
+        }
+    }
+
+    /// Sort predictions by departure time. The API does this for polled
+    /// responses, but streamed updates arrive in any order
+    fn sort(&mut self) {
+        self.data
+            .sort_by_key(|prediction| prediction.attributes.departure_time);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn event(event: &str, data: serde_json::Value) -> StreamEvent {
+        StreamEvent::parse(&ServerSentEvent {
+            event: event.into(),
+            data: data.to_string(),
+        })
+        .unwrap()
+        .unwrap()
+    }
+
+    fn prediction(id: &str, departure_time: &str) -> serde_json::Value {
+        json!({
+            "type": "prediction",
+            "id": id,
+            "attributes": {"departure_time": departure_time},
+            "relationships": {
+                "route": {"data": {"id": "Red", "type": "route"}},
+                "stop": {"data": {"id": "70080", "type": "stop"}},
+            },
+        })
+    }
+
+    /// Get the IDs of all predictions, in order
+    fn ids(predictions: &ApiPredictions) -> Vec<&str> {
+        predictions
+            .data
+            .iter()
+            .map(|prediction| prediction.id.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_apply() {
+        let StreamEvent::Reset(mut predictions) = event(
+            "reset",
+            json!([
+                prediction("b", "2025-06-12T08:10:00-04:00"),
+                {
+                    "type": "stop",
+                    "id": "70080",
+                    "relationships": {
+                        "parent_station": {"data": {"id": "place-sstat"}},
+                    },
+                },
+                prediction("a", "2025-06-12T08:05:00-04:00"),
+            ]),
+        ) else {
+            panic!("Expected reset event");
+        };
+        assert_eq!(ids(&predictions), ["a", "b"]);
+        assert_eq!(
+            predictions.parent_stations(),
+            [("70080", "place-sstat")].into()
+        );
+
+        // Updates are re-sorted
+        predictions
+            .apply(event("add", prediction("c", "2025-06-12T08:01:00-04:00")));
+        predictions.apply(event(
+            "update",
+            prediction("a", "2025-06-12T08:15:00-04:00"),
+        ));
+        assert_eq!(ids(&predictions), ["c", "b", "a"]);
+
+        predictions
+            .apply(event("remove", json!({"type": "prediction", "id": "b"})));
+        predictions
+            .apply(event("remove", json!({"type": "stop", "id": "70080"})));
+        assert_eq!(ids(&predictions), ["c", "a"]);
+        assert_eq!(predictions.parent_stations(), HashMap::new());
+    }
+}
@@ -1,12 +1,19 @@
+mod point;
+
+pub use point::WeatherPoint;
+
 use crate::{
     Message,
     config::Config,
-    services::{CLIENT, ExternalData, FetchedData},
+    services::{
+        CLIENT, DataStore, ExternalData, FetchResult, RetryPolicy,
+        http::fetch_json,
+    },
 };
 use anyhow::Context;
 use chrono::{DateTime, Local, NaiveTime, Utc};
 use log::info;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::time::Duration;
 
 const API_HOST: &str = "https://api.weather.gov";
@@ -19,88 +26,201 @@ const PERIOD_INTERNAL: usize = 4;
 /// Fetch weather data from the weather.gov API
 #[derive(Debug)]
 pub struct Weather {
-    url: String,
-    data: Option<FetchedData<Forecast>>,
+    /// Hourly forecast URL. `None` until the configured location has been
+    /// resolved to a gridpoint
+    url: Option<String>,
+    store: DataStore<Forecast>,
 }
 
 impl Weather {
-    pub fn new(config: &Config) -> Self {
-        let url = format!(
-            "{}/gridpoints/{}/{},{}/forecast/hourly",
-            API_HOST,
-            config.forecast_office,
-            config.forecast_gridpoint.0,
-            config.forecast_gridpoint.1
-        );
-        Self { url, data: None }
+    pub fn new(config: &Config, point: &WeatherPoint) -> Self {
+        Self {
+            url: point.forecast_url(),
+            store: DataStore::new(
+                config.refresh.schedule::<Self>(),
+                config.cache_path::<Self>(),
+            ),
+        }
+    }
+
+    /// Switch to the forecast URL of a newly resolved gridpoint. If it
+    /// changed, the forecast for the old URL is discarded
+    pub fn set_point(&mut self, point: &WeatherPoint) {
+        let new = Self {
+            url: point.forecast_url(),
+            store: DataStore::new(
+                self.store.refresh.clone(),
+                self.store.cache_path.clone(),
+            ),
+        };
+        self.reload(new);
     }
 
     pub fn forecast(&self) -> Option<&Forecast> {
-        self.data.as_ref().map(|data| &data.data)
+        self.data().map(|data| &data.data)
     }
 }
 
 impl ExternalData for Weather {
-    const TTL: Duration = Duration::from_secs(60);
+    const NAME: &'static str = "weather";
+    const DEFAULT_TTL: Duration = Duration::from_secs(60);
+    // weather.gov throttles aggressive clients, so back off harder
+    const RETRY_POLICY: RetryPolicy = RetryPolicy {
+        initial: Duration::from_secs(30),
+        max: Duration::from_secs(15 * 60),
+    };
     type Data = Forecast;
 
-    fn data(&self) -> Option<&FetchedData<Self::Data>> {
-        self.data.as_ref()
+    fn same_settings(&self, other: &Self) -> bool {
+        self.url == other.url && self.store.refresh == other.store.refresh
+    }
+
+    fn cache_key(&self) -> String {
+        self.url.clone().unwrap_or_default()
+    }
+
+    fn store(&self) -> &DataStore<Self::Data> {
+        &self.store
+    }
+
+    fn store_mut(&mut self) -> &mut DataStore<Self::Data> {
+        &mut self.store
     }
 
-    fn set_data(&mut self, data: FetchedData<Self::Data>) {
-        self.data = Some(data);
+    fn result_to_message(result: FetchResult<Self::Data>) -> Message {
+        Message::WeatherFetched(result)
     }
 
-    fn data_to_message(data: FetchedData<Self::Data>) -> Message {
-        Message::WeatherFetched(data)
+    fn is_enabled(&self) -> bool {
+        self.url.is_some()
     }
 
     fn fetch(
         &self,
     ) -> impl 'static + Future<Output = anyhow::Result<Self::Data>> + Send {
-        info!("Fetching weather data from {}", self.url);
-        let request = CLIENT.get(&self.url);
+        let url = self.url.clone().unwrap_or_default();
+        info!("Fetching weather data from {url}");
+        let request = CLIENT.get(&url);
         async move {
-            let response =
-                request.send().await.context("Error fetching weather")?;
-            response
-                .error_for_status()?
-                .json()
+            fetch_json(Self::NAME, request)
                 .await
-                .context("Error parsing weather")
+                .context("Error fetching weather")
         }
     }
 }
 
-///https://www.weather.gov/documentation/services-web-api#/default/gridpoint_forecast
-#[derive(Clone, Debug, Deserialize)]
+/// <https://www.weather.gov/documentation/services-web-api#/default/gridpoint_forecast>
+#[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Forecast {
     properties: ForecastProperties,
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ForecastProperties {
     periods: Vec<ForecastPeriod>,
 }
 
-#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ForecastPeriod {
     start_time: DateTime<Utc>,
     end_time: DateTime<Utc>,
+    is_daytime: bool,
     temperature: i32,
     probability_of_precipitation: Unit,
+    relative_humidity: Unit,
+    dewpoint: Measurement,
+    /// e.g. `5 mph` or `5 to 10 mph`
+    wind_speed: String,
+    /// Compass point the wind is blowing from, e.g. `SW`
+    wind_direction: String,
+    /// e.g. `Partly Sunny`
+    short_forecast: String,
+    /// URL of an NWS icon, e.g.
+    /// `https://api.weather.gov/icons/land/day/tsra_sct,20?size=small`
+    icon: String,
 }
 
-#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Unit {
     pub value: Option<i32>,
 }
 
+/// A value that may not be in the unit we display, e.g. dewpoint, which is
+/// always given in Celsius
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Measurement {
+    /// e.g. `wmoUnit:degC`
+    pub unit_code: String,
+    pub value: Option<f64>,
+}
+
+/// Weather condition, parsed from an NWS icon code. Several codes can map to
+/// the same condition
+/// <https://api.weather.gov/icons>
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Condition {
+    Clear,
+    PartlyCloudy,
+    MostlyCloudy,
+    Overcast,
+    Windy,
+    Rain,
+    Showers,
+    Thunderstorms,
+    Snow,
+    /// Sleet, freezing rain, or a mix of rain and snow
+    Sleet,
+    Fog,
+    /// Haze, smoke, or dust
+    Haze,
+    Hot,
+    Cold,
+    Tornado,
+    /// Hurricane or tropical storm
+    Hurricane,
+}
+
+impl Condition {
+    /// Parse the code of an NWS icon. Icons for mixed conditions list more
+    /// than one code (e.g. `rain/tsra`), in which case the first is used
+    fn from_icon_url(url: &str) -> Option<Self> {
+        let path = url.split('?').next()?;
+        // Path is /icons/land/{day|night}/{code}[,{pop}][/{code}[,{pop}]]
+        let (_, icon) = path
+            .split_once("/day/")
+            .or_else(|| path.split_once("/night/"))?;
+        let code = icon.split(['/', ',']).next()?;
+        let condition = match code {
+            "skc" => Self::Clear,
+            "few" | "sct" => Self::PartlyCloudy,
+            "bkn" => Self::MostlyCloudy,
+            "ovc" => Self::Overcast,
+            "wind_skc" | "wind_few" | "wind_sct" | "wind_bkn" | "wind_ovc" => {
+                Self::Windy
+            }
+            "rain" => Self::Rain,
+            "rain_showers" | "rain_showers_hi" => Self::Showers,
+            "tsra" | "tsra_sct" | "tsra_hi" => Self::Thunderstorms,
+            "snow" | "blizzard" => Self::Snow,
+            "rain_snow" | "rain_sleet" | "snow_sleet" | "fzra"
+            | "rain_fzra" | "snow_fzra" | "sleet" => Self::Sleet,
+            "fog" => Self::Fog,
+            "haze" | "smoke" | "dust" => Self::Haze,
+            "hot" => Self::Hot,
+            "cold" => Self::Cold,
+            "tornado" => Self::Tornado,
+            "hurricane" | "tropical_storm" => Self::Hurricane,
+            _ => return None,
+        };
+        Some(condition)
+    }
+}
+
 impl Forecast {
     /// Get the current forecast period
     pub fn now(&self) -> &ForecastPeriod {
@@ -140,11 +260,64 @@ impl ForecastPeriod {
             self.probability_of_precipitation.value.unwrap_or_default()
         )
     }
+
+    /// Is this period during the day, as opposed to night?
+    pub fn is_daytime(&self) -> bool {
+        self.is_daytime
+    }
+
+    /// Formatted relative humidity
+    pub fn humidity(&self) -> String {
+        format!("{}%", self.relative_humidity.value.unwrap_or_default())
+    }
+
+    /// Formatted dewpoint, in Fahrenheit to match the temperature. `None` if
+    /// the forecast doesn't have one
+    pub fn dewpoint(&self) -> Option<String> {
+        let value = self.dewpoint.value?;
+        let fahrenheit = if self.dewpoint.unit_code == "wmoUnit:degC" {
+            value * 9.0 / 5.0 + 32.0
+        } else {
+            value
+        };
+        Some(format!("{fahrenheit:.0}°"))
+    }
+
+    /// Wind speed, e.g. `5 mph`
+    pub fn wind_speed(&self) -> &str {
+        &self.wind_speed
+    }
+
+    /// Direction the wind is blowing toward, in degrees clockwise from north.
+    /// This is opposite the reported direction, which is where the wind is
+    /// coming from. `None` if the direction is missing or unknown
+    pub fn wind_bearing(&self) -> Option<f64> {
+        const COMPASS_POINTS: [&str; 16] = [
+            "N", "NNE", "NE", "ENE", "E", "ESE", "SE", "SSE", "S", "SSW", "SW",
+            "WSW", "W", "WNW", "NW", "NNW",
+        ];
+        let index = COMPASS_POINTS
+            .iter()
+            .position(|point| *point == self.wind_direction)?;
+        Some((index as f64 * 22.5 + 180.0) % 360.0)
+    }
+
+    /// Short description of the conditions, e.g. `Partly Sunny`
+    pub fn short_forecast(&self) -> &str {
+        &self.short_forecast
+    }
+
+    /// Weather condition, for picking an icon. `None` if the forecast has an
+    /// unknown icon
+    pub fn condition(&self) -> Option<Condition> {
+        Condition::from_icon_url(&self.icon)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::services::test_util::load_fixture;
 
     fn period(
         time: &str,
@@ -161,6 +334,7 @@ mod tests {
             probability_of_precipitation: Unit {
                 value: Some(probability_of_precipitation),
             },
+            ..ForecastPeriod::default()
         }
     }
 
@@ -178,4 +352,63 @@ mod tests {
 
         assert_eq!(forecast.now(), &period("2024-05-24T17:00:00Z", 1, 84, 1));
     }
+
+    /// Future periods should be every nth period after the current one,
+    /// skipping the middle of the night
+    #[test]
+    fn test_future_periods() {
+        let forecast: Forecast = load_fixture("weather.json");
+
+        let future: Vec<_> = forecast.future_periods().collect();
+        assert!(!future.is_empty());
+        for period in future {
+            let index = forecast
+                .properties
+                .periods
+                .iter()
+                .position(|p| p == period)
+                .unwrap();
+            assert_eq!(index % PERIOD_INTERNAL, 1);
+            assert!(
+                (DAY_START..=DAY_END).contains(&period.start_time().time()),
+                "{period:?} is outside the day"
+            );
+        }
+    }
+
+    #[test]
+    fn test_period_details() {
+        let forecast: Forecast = load_fixture("weather.json");
+
+        let now = forecast.now();
+        assert!(now.is_daytime());
+        assert_eq!(now.short_forecast(), "Sunny");
+        assert_eq!(now.humidity(), "41%");
+        // 12°C
+        assert_eq!(now.dewpoint().as_deref(), Some("54°"));
+        assert_eq!(now.wind_speed(), "5 mph");
+        // From the southwest, so blowing northeast
+        assert_eq!(now.wind_bearing(), Some(45.0));
+        assert_eq!(now.condition(), Some(Condition::PartlyCloudy));
+    }
+
+    #[test]
+    fn test_condition() {
+        let condition = |icon: &str| {
+            Condition::from_icon_url(&format!(
+                "https://api.weather.gov/icons/land/{icon}?size=small"
+            ))
+        };
+        assert_eq!(condition("night/skc"), Some(Condition::Clear));
+        assert_eq!(condition("day/wind_bkn"), Some(Condition::Windy));
+        // Chance of precipitation is ignored
+        assert_eq!(
+            condition("day/tsra_sct,20"),
+            Some(Condition::Thunderstorms)
+        );
+        // The first of mixed conditions is used
+        assert_eq!(condition("day/rain,40/snow,60"), Some(Condition::Rain));
+        assert_eq!(condition("day/unknown"), None);
+        assert_eq!(Condition::from_icon_url(""), None);
+    }
 }
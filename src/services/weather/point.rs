@@ -0,0 +1,197 @@
+use crate::{
+    Message,
+    config::Config,
+    services::{
+        CLIENT, DataStore, ExternalData, FetchResult, RetryPolicy,
+        http::fetch_json,
+        weather::{API_HOST, Weather},
+    },
+};
+use anyhow::Context;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Resolve the configured latitude and longitude to the NWS forecast office
+/// and gridpoint that cover it. Disabled if the office and gridpoint are set
+/// in the config
+#[derive(Debug)]
+pub struct WeatherPoint {
+    /// Forecast URL built from the configured office and gridpoint, which
+    /// skips the lookup
+    override_url: Option<String>,
+    /// URL of the points endpoint. `None` if the lookup is overridden or no
+    /// location is configured
+    url: Option<String>,
+    store: DataStore<Point>,
+}
+
+impl WeatherPoint {
+    pub fn new(config: &Config) -> Self {
+        let override_url = config
+            .forecast_office
+            .as_ref()
+            .zip(config.forecast_gridpoint)
+            .map(|(office, (x, y))| {
+                format!(
+                    "{API_HOST}/gridpoints/{office}/{x},{y}/forecast/hourly"
+                )
+            });
+        // The API redirects requests with more than 4 decimal places
+        let url = config
+            .latitude
+            .zip(config.longitude)
+            .filter(|_| override_url.is_none())
+            .map(|(latitude, longitude)| {
+                format!("{API_HOST}/points/{latitude:.4},{longitude:.4}")
+            });
+        Self {
+            override_url,
+            url,
+            store: DataStore::new(
+                config.refresh.schedule::<Self>(),
+                config.cache_path::<Self>(),
+            ),
+        }
+    }
+
+    /// Get the URL of the hourly forecast. `None` if the location hasn't been
+    /// resolved yet
+    pub fn forecast_url(&self) -> Option<String> {
+        self.override_url.clone().or_else(|| {
+            self.data()
+                .map(|data| data.data.properties.forecast_hourly.clone())
+        })
+    }
+}
+
+impl ExternalData for WeatherPoint {
+    const NAME: &'static str = "weather_point";
+    // Gridpoints only change when NWS redraws its grids, which is rare. The
+    // cached point is used on startup, so this is effectively fetched once
+    // per run
+    const DEFAULT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+    const RETRY_POLICY: RetryPolicy = Weather::RETRY_POLICY;
+    type Data = Point;
+
+    fn same_settings(&self, other: &Self) -> bool {
+        self.override_url == other.override_url
+            && self.url == other.url
+            && self.store.refresh == other.store.refresh
+    }
+
+    fn cache_key(&self) -> String {
+        self.url.clone().unwrap_or_default()
+    }
+
+    fn store(&self) -> &DataStore<Self::Data> {
+        &self.store
+    }
+
+    fn store_mut(&mut self) -> &mut DataStore<Self::Data> {
+        &mut self.store
+    }
+
+    fn result_to_message(result: FetchResult<Self::Data>) -> Message {
+        Message::WeatherPointFetched(result)
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.url.is_some()
+    }
+
+    fn fetch(
+        &self,
+    ) -> impl 'static + Future<Output = anyhow::Result<Self::Data>> + Send {
+        let url = self.url.clone().unwrap_or_default();
+        info!("Resolving weather gridpoint from {url}");
+        let request = CLIENT.get(&url);
+        async move {
+            let point: Point = fetch_json(Self::NAME, request)
+                .await
+                .context("Error resolving weather gridpoint")?;
+            let properties = &point.properties;
+            info!(
+                "Resolved weather gridpoint to {}/{},{}",
+                properties.grid_id, properties.grid_x, properties.grid_y
+            );
+            Ok(point)
+        }
+    }
+}
+
+/// <https://www.weather.gov/documentation/services-web-api#/default/point>
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Point {
+    properties: PointProperties,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PointProperties {
+    /// Forecast office, e.g. `BOX`
+    grid_id: String,
+    grid_x: u32,
+    grid_y: u32,
+    forecast_hourly: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::{
+        FetchedData,
+        test_util::{config, load_fixture},
+    };
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_forecast_url() {
+        let point: Point = load_fixture("weather_point.json");
+        let cache_dir = TempDir::new().unwrap();
+
+        let mut resolved = WeatherPoint::new(&config(json!({
+            "latitude": 42.360_082_1,
+            "longitude": -71.058_88,
+            "cache_dir": cache_dir.path(),
+        })));
+        assert_eq!(
+            resolved.url.as_deref(),
+            Some("https://api.weather.gov/points/42.3601,-71.0589")
+        );
+        assert_eq!(resolved.forecast_url(), None);
+        // A lookup started for a previous location is dropped
+        resolved.handle_result(FetchResult {
+            key: "https://api.weather.gov/points/40.7128,-74.0060".into(),
+            result: Ok(FetchedData::new(point.clone())),
+        });
+        assert_eq!(resolved.forecast_url(), None);
+        resolved.handle_result(FetchResult {
+            key: resolved.cache_key(),
+            result: Ok(FetchedData::new(point)),
+        });
+        assert_eq!(
+            resolved.forecast_url().as_deref(),
+            Some(
+                "https://api.weather.gov/gridpoints/BOX/71,90/forecast/hourly"
+            )
+        );
+
+        // The office and gridpoint skip the lookup
+        let overridden = WeatherPoint::new(&config(json!({
+            "latitude": 42.3601,
+            "longitude": -71.0589,
+            "forecast_office": "OKX",
+            "forecast_gridpoint": [33, 35],
+        })));
+        assert!(!overridden.is_enabled());
+        assert_eq!(
+            overridden.forecast_url().as_deref(),
+            Some(
+                "https://api.weather.gov/gridpoints/OKX/33,35/forecast/hourly"
+            )
+        );
+    }
+}
@@ -1,58 +1,274 @@
 use crate::{
     Message, State, Tab,
     services::{
-        transit::{LinePrediction, Predictions},
-        weather::Forecast,
+        DataStore, ExternalData,
+        bikes::StationAvailability,
+        transit::{
+            Alert, Alerts, Countdown, CountdownList, LinePrediction,
+            Predictions,
+        },
+        weather::{Condition, Forecast, ForecastPeriod},
     },
 };
+use chrono::Local;
 use iced::{
-    Element, Length, Padding,
-    alignment::Horizontal,
-    widget::{Column, Container, text},
+    Element, Length, Padding, Theme,
+    alignment::{Horizontal, Vertical},
+    theme::palette,
+    widget::{Column, Container, Row, container, text},
 };
 use iced_aw::{Grid, TabBar, TabLabel, grid_row};
+use iced_fonts::{BOOTSTRAP_FONT, Bootstrap};
+use std::time::Duration;
 
+const FONT_SIZE_SMALL: f32 = 18.0;
 const FONT_SIZE_MEDIUM: f32 = 32.0;
 const FONT_SIZE_LARGE: f32 = 48.0;
 
 /// Generate display elements
 pub fn view(state: &State) -> Element<Message> {
     // Build the tab bar
-    let tabs = Tab::iter()
+    let tabs = state
+        .tabs()
         .fold(TabBar::new(Message::TabSelected), |tab_bar, tab| {
-            tab_bar.push(tab, TabLabel::Text(tab.to_string()))
+            // Flag tabs whose data source is currently failing
+            let mut label = if tab_has_error(state, tab) {
+                format!("{tab} (!)")
+            } else {
+                tab.to_string()
+            };
+            match tab_alert_count(state, tab) {
+                0 => {}
+                1 => label += " (1 alert)",
+                count => label += &format!(" ({count} alerts)"),
+            }
+            tab_bar.push(tab, TabLabel::Text(label))
         })
         .set_active_tab(&state.active_tab)
         // Fill the entire screen evenly
-        .tab_width(Length::FillPortion(Tab::iter().count() as u16))
+        .tab_width(Length::FillPortion(state.tabs().count() as u16))
         .padding(5.0)
         .text_size(FONT_SIZE_MEDIUM);
-    let content = match state.active_tab {
+    let (status, alerts, content) = match state.active_tab {
         Tab::Weather => {
-            if let Some(forecast) = state.weather.forecast() {
+            let content = if let Some(forecast) = state.weather.forecast() {
                 view_weather(forecast)
             } else {
                 text("Loading...").into()
-            }
+            };
+            // Show the status of the forecast over the gridpoint lookup
+            let status = view_status(state.weather.store())
+                .or_else(|| view_status(state.weather_point.store()));
+            (status, None, content)
         }
         Tab::Transit => {
-            let predictions = state.transit.predictions();
-            view_transit(predictions)
+            let predictions = state
+                .transit
+                .predictions(&state.schedules, &state.gtfs_static);
+            // Schedules, alerts, and the static feed are secondary, so prefer
+            // showing the status of predictions
+            let status = view_status(state.transit.store())
+                .or_else(|| view_status(state.schedules.store()))
+                .or_else(|| view_status(state.alerts.store()))
+                .or_else(|| view_status(state.gtfs_static.store()));
+            (
+                status,
+                view_alerts(&state.alerts),
+                view_transit(predictions),
+            )
+        }
+        Tab::Bikes => {
+            let content = if let Some(stations) = state.bikes.stations() {
+                view_bikes(stations)
+            } else {
+                text("Loading...").into()
+            };
+            (view_status(state.bikes.store()), None, content)
         }
     };
+    let toast = state.toast.as_ref().map(|toast| {
+        banner(text(toast.message.clone()), |palette| palette.danger.weak)
+    });
     Column::new()
         .push(tabs)
-        .push(Container::new(content).padding(16.0))
+        .push_maybe(toast)
+        .push_maybe(status)
+        .push_maybe(alerts)
+        .push(Container::new(content).padding(16.0).height(Length::Fill))
+        .push(view_footer(state))
+        .into()
+}
+
+/// Show how long ago each data source was updated
+fn view_footer(state: &State) -> Element<'static, Message> {
+    fn view_updated(
+        name: &str,
+        service: &impl ExternalData,
+        stale_multiplier: f64,
+    ) -> Element<'static, Message> {
+        let mut label = match service.store().last_success() {
+            _ if service.is_streaming() => format!("{name} live"),
+            Some(elapsed) => format!("{name} updated {}", format_ago(elapsed)),
+            None => format!("{name} not updated yet"),
+        };
+        // Let the user know why updates are slower than usual
+        if let Some(interval) = service.throttle() {
+            label += &format!(" (throttled to every {}s)", interval.as_secs());
+        }
+        let label = text(label).size(FONT_SIZE_SMALL);
+        if service.is_outdated(stale_multiplier) {
+            label.style(text::danger).into()
+        } else {
+            label.style(text::secondary).into()
+        }
+    }
+
+    Container::new(
+        Row::new()
+            .push(view_updated(
+                "Weather",
+                &state.weather,
+                state.stale_multiplier,
+            ))
+            .push(view_updated(
+                "Transit",
+                &state.transit,
+                state.stale_multiplier,
+            ))
+            .push_maybe(state.bikes.is_enabled().then(|| {
+                view_updated("Bikes", &state.bikes, state.stale_multiplier)
+            }))
+            .spacing(16.0),
+    )
+    .padding(Padding::from([4.0, 16.0]))
+    .into()
+}
+
+/// Is the data source for the given tab in an error state?
+fn tab_has_error(state: &State, tab: Tab) -> bool {
+    match tab {
+        Tab::Weather => {
+            state.weather.store().error().is_some()
+                || state.weather_point.store().error().is_some()
+        }
+        Tab::Transit => {
+            state.transit.store().error().is_some()
+                || state.schedules.store().error().is_some()
+                || state.alerts.store().error().is_some()
+                || state.gtfs_static.store().error().is_some()
+        }
+        Tab::Bikes => state.bikes.store().error().is_some(),
+    }
+}
+
+/// Get the number of active service alerts relevant to the given tab
+fn tab_alert_count(state: &State, tab: Tab) -> usize {
+    match tab {
+        Tab::Weather | Tab::Bikes => 0,
+        Tab::Transit => state.alerts.alerts().len(),
+    }
+}
+
+/// Show a banner with all active service alerts, if there are any. The banner
+/// is highlighted if any alert is severe
+fn view_alerts(alerts: &Alerts) -> Option<Element<'static, Message>> {
+    let alerts = alerts.alerts();
+    if alerts.is_empty() {
+        return None;
+    }
+    let content = Column::new().extend(alerts.iter().map(|alert| {
+        text(format!(
+            "{} (severity {}): {}",
+            alert.effect(),
+            alert.severity(),
+            alert.header()
+        ))
+        .into()
+    }));
+    let color = if alerts.iter().any(Alert::is_severe) {
+        |palette: &palette::Extended| palette.danger.weak
+    } else {
+        |palette: &palette::Extended| palette.primary.weak
+    };
+    Some(banner(content, color))
+}
+
+/// Show a banner with the status of a data source, if there's anything the
+/// user should know about. That's either the most recent fetch error, or that
+/// the data shown was loaded from the cache and is stale
+fn view_status<T>(store: &DataStore<T>) -> Option<Element<'static, Message>> {
+    let Some(error) = store.error() else {
+        let cached_since = store.cached_since()?;
+        return Some(banner(
+            text(format!(
+                "Stale since {}",
+                cached_since.with_timezone(&Local).format("%_H:%M")
+            )),
+            |palette| palette.secondary.weak,
+        ));
+    };
+    let last_success = match store.last_success() {
+        Some(elapsed) => format!("Last success {}", format_ago(elapsed)),
+        None => "No successful fetch yet".to_owned(),
+    };
+    let content = Column::new()
+        .push(text(error.to_string()))
+        .push(text(last_success));
+    Some(banner(content, |palette| palette.danger.weak))
+}
+
+/// Full-width colored box to draw attention to a message
+fn banner<'a>(
+    content: impl Into<Element<'a, Message>>,
+    color: fn(&palette::Extended) -> palette::Pair,
+) -> Element<'a, Message> {
+    Container::new(content)
+        .width(Length::Fill)
+        .padding(8.0)
+        .style(move |theme: &Theme| {
+            let pair = color(theme.extended_palette());
+            container::Style {
+                background: Some(pair.color.into()),
+                text_color: Some(pair.text),
+                ..container::Style::default()
+            }
+        })
         .into()
 }
 
+/// Format an elapsed duration as "12s ago", "5m ago", etc.
+fn format_ago(elapsed: Duration) -> String {
+    let seconds = elapsed.as_secs();
+    if seconds < 60 {
+        format!("{seconds}s ago")
+    } else if seconds < 60 * 60 {
+        format!("{}m ago", seconds / 60)
+    } else {
+        format!("{}h ago", seconds / (60 * 60))
+    }
+}
+
 /// Generate elements for the weather forecast
 fn view_weather(forecast: &Forecast) -> Element<'_, Message> {
     // Now
     let now = forecast.now();
-    let now_text =
-        text(format!("{} / {}", now.temperature(), now.prob_of_precip()))
-            .size(FONT_SIZE_LARGE);
+    let now_header = Row::new()
+        .push(view_condition(now).size(FONT_SIZE_LARGE))
+        .push(
+            text(format!("{} / {}", now.temperature(), now.prob_of_precip()))
+                .size(FONT_SIZE_LARGE),
+        )
+        .spacing(16.0)
+        .align_y(Vertical::Center);
+    let mut now_details =
+        Row::new().push(text(format!("Humidity {}", now.humidity())));
+    if let Some(dewpoint) = now.dewpoint() {
+        now_details = now_details.push(text(format!("Dew point {dewpoint}")));
+    }
+    let now_details = now_details
+        .push(view_wind(now))
+        .spacing(16.0)
+        .align_y(Vertical::Center);
 
     // Later
     let num_future_periods = 8;
@@ -63,17 +279,79 @@ fn view_weather(forecast: &Forecast) -> Element<'_, Message> {
             .map(|period| {
                 grid_row!(
                     text(format!("{}", period.start_time().format("%_I%P"))),
+                    view_condition(period),
                     text(period.temperature()),
                     text(period.prob_of_precip()),
+                    view_wind(period),
                 )
             })
             .collect(),
     )
     .padding(Padding::ZERO.top(8.0))
     .horizontal_alignment(Horizontal::Right)
+    .vertical_alignment(Vertical::Center)
     .column_spacing(8.0);
 
-    Column::new().push(now_text).push(future_grid).into()
+    Column::new()
+        .push(now_header)
+        .push(text(now.short_forecast()))
+        .push(now_details)
+        .push(future_grid)
+        .into()
+}
+
+/// Icon for a forecast period's weather condition. Empty if the condition is
+/// unknown
+fn view_condition(period: &ForecastPeriod) -> text::Text<'static> {
+    let icon = period.condition().map(|condition| match condition {
+        Condition::Clear if period.is_daytime() => Bootstrap::SunFill,
+        Condition::Clear => Bootstrap::MoonFill,
+        Condition::PartlyCloudy if period.is_daytime() => {
+            Bootstrap::CloudSunFill
+        }
+        Condition::PartlyCloudy => Bootstrap::CloudMoonFill,
+        Condition::MostlyCloudy => Bootstrap::CloudsFill,
+        Condition::Overcast => Bootstrap::CloudyFill,
+        Condition::Windy => Bootstrap::Wind,
+        Condition::Rain => Bootstrap::CloudRainFill,
+        Condition::Showers => Bootstrap::CloudDrizzleFill,
+        Condition::Thunderstorms => Bootstrap::CloudLightningRainFill,
+        Condition::Snow => Bootstrap::CloudSnowFill,
+        Condition::Sleet => Bootstrap::CloudSleetFill,
+        Condition::Fog => Bootstrap::CloudFogFill,
+        Condition::Haze => Bootstrap::CloudHazeFill,
+        Condition::Hot => Bootstrap::ThermometerSun,
+        Condition::Cold => Bootstrap::ThermometerSnow,
+        Condition::Tornado => Bootstrap::Tornado,
+        Condition::Hurricane => Bootstrap::Hurricane,
+    });
+    text(icon.map(|icon| icon.to_string()).unwrap_or_default())
+        .font(BOOTSTRAP_FONT)
+}
+
+/// Wind speed, with an arrow pointing the way the wind is blowing
+fn view_wind(period: &ForecastPeriod) -> Element<'static, Message> {
+    // Round to the nearest of the 8 arrows, clockwise from north
+    const ARROWS: [Bootstrap; 8] = [
+        Bootstrap::ArrowUp,
+        Bootstrap::ArrowUpRight,
+        Bootstrap::ArrowRight,
+        Bootstrap::ArrowDownRight,
+        Bootstrap::ArrowDown,
+        Bootstrap::ArrowDownLeft,
+        Bootstrap::ArrowLeft,
+        Bootstrap::ArrowUpLeft,
+    ];
+    let arrow = period.wind_bearing().map(|bearing| {
+        let index = ((bearing + 22.5) / 45.0) as usize % ARROWS.len();
+        text(ARROWS[index].to_string()).font(BOOTSTRAP_FONT)
+    });
+    Row::new()
+        .push_maybe(arrow)
+        .push(text(period.wind_speed().to_owned()))
+        .spacing(4.0)
+        .align_y(Vertical::Center)
+        .into()
 }
 
 /// Display transit predictions
@@ -88,7 +366,7 @@ fn view_transit(predictions: Predictions) -> Element<'static, Message> {
                         .map(|stop| {
                             grid_row!(
                                 text(stop.name),
-                                text(stop.predictions.to_string())
+                                view_countdowns(&stop.predictions)
                             )
                         })
                         .collect(),
@@ -102,3 +380,79 @@ fn view_transit(predictions: Predictions) -> Element<'static, Message> {
         .extend(predictions.lines.into_iter().map(view_line))
         .into()
 }
+
+/// Display upcoming departures for a stop, one per row, with the trip's
+/// destination and how crowded the vehicle is
+fn view_countdowns(countdowns: &CountdownList) -> Element<'static, Message> {
+    fn view_countdown(countdown: &Countdown) -> Element<'static, Message> {
+        let time = text(countdown.to_string());
+        // Trips that won't stop here are shown so they aren't a surprise, but
+        // they shouldn't look like a bus we can catch
+        let time = if countdown.is_removed() {
+            time.style(text::danger)
+        } else if countdown.is_missed() {
+            time.style(text::secondary)
+        } else {
+            time
+        };
+        Row::new()
+            .push(time)
+            .push_maybe(countdown.headsign().map(|headsign| {
+                text(headsign.to_owned()).size(FONT_SIZE_SMALL)
+            }))
+            .push_maybe(
+                countdown
+                    .occupancy()
+                    .and_then(|occupancy| occupancy.icon())
+                    .map(|icon| text(icon).size(FONT_SIZE_SMALL)),
+            )
+            .spacing(8.0)
+            .align_y(Vertical::Center)
+            .into()
+    }
+
+    Column::new()
+        .extend(countdowns.iter().map(view_countdown))
+        .into()
+}
+
+/// Display bike and dock availability at each bike share station. Counts that
+/// are zero are highlighted, so an empty or full station stands out
+fn view_bikes(stations: Vec<StationAvailability>) -> Element<'static, Message> {
+    fn count(count: u32, highlight: bool) -> Element<'static, Message> {
+        let count = text(count);
+        if highlight {
+            count.style(text::danger).into()
+        } else {
+            count.into()
+        }
+    }
+
+    let header = grid_row!(
+        text(""),
+        text("Bikes").size(FONT_SIZE_SMALL).style(text::secondary),
+        text("E-bikes").size(FONT_SIZE_SMALL).style(text::secondary),
+        text("Docks").size(FONT_SIZE_SMALL).style(text::secondary),
+    );
+    let rows = stations.into_iter().map(|station| {
+        // Closed stations can't be used either way, so their counts aren't
+        // highlighted
+        let is_empty = station.is_open && station.is_empty();
+        let is_full = station.is_open && station.is_full();
+        let name = if station.is_open {
+            text(station.name)
+        } else {
+            text(format!("{} (closed)", station.name)).style(text::secondary)
+        };
+        grid_row!(
+            name,
+            count(station.bikes, is_empty),
+            count(station.ebikes, is_empty),
+            count(station.docks, is_full),
+        )
+    });
+    Grid::with_rows(std::iter::once(header).chain(rows).collect())
+        .horizontal_alignment(Horizontal::Right)
+        .column_spacing(16.0)
+        .into()
+}